@@ -2,27 +2,32 @@
 #![allow(clippy::expect_fun_call)]
 
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    sync::atomic::{AtomicUsize, Ordering},
-    time::{Duration, SystemTime},
+    collections::{HashMap, HashSet},
+    time::Duration,
 };
 
 use aurora::*;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, trace};
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), Error> {
+    init_logging();
     main_loop::<BroadcastNode>().await
 }
 
 const SENDER_UNWRAP: &str = "expected for sender over channel to succeed";
-static MESSAGE_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// How long to wait for a `BroadcastOk` before resending a broadcast message
+const RESEND_TIMEOUT: Duration = Duration::from_millis(150);
+/// The longest to wait between resends while a neighbor is unreachable
+const MAX_RESEND_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often to check for broadcast messages that need to be resent
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
 
 /// Approach:
 /// This is taking an immediate-mode approach to gossiping. That is, as soon as a message is
 /// received, outbound messages are sent to our adjecents. We then wait for a `BroadcastOk` message
 /// to be received. If no such message is recieved for ... some amount of time, we resend that
-/// broadcast message.
+/// broadcast message, backing off while the neighbor stays unreachable.
 ///
 /// NOTE: In a real-world usecase, we would also want to try and detect if a node in our
 /// district has gone down so that we can update our district and/or report the problem.
@@ -30,13 +35,11 @@ static MESSAGE_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 /// still worth keeping in mind.
 #[derive(Debug)]
 struct BroadcastNode {
-    id: String,
-    counter: MessageIdCounter,
     messages: HashSet<usize>,
-    sender: UnboundedSender<Message<BroadcastBody>>,
     // The ids of adjecents mapped to the messages we know they have
     adjecents: HashMap<String, Adjecent>,
-    tracker: UnboundedSender<TrackerAction>,
+    // The broadcast messages that have not been acknowledged
+    outstanding: Reliable<BroadcastBody>,
 }
 
 #[derive(Debug, Default, PartialEq)]
@@ -48,41 +51,22 @@ struct Adjecent {
     pending: HashMap<MessageId, usize>,
 }
 
-#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
-struct MessageIdCounter;
-
-#[derive(Debug, PartialEq, Eq)]
-enum TrackerAction {
-    Track(Message<BroadcastBody>),
-    Stop(MessageId),
-}
-
 impl Node for BroadcastNode {
     type Body = BroadcastBody;
 
-    fn init(
-        sender: UnboundedSender<Message<Self::Body>>,
-        node_id: String,
-        nodes: Vec<String>,
-    ) -> Self {
-        let adjecents = HashMap::with_capacity(nodes.len());
-        let tracker_sender = sender.clone();
-        let (tracker, recv) = unbounded_channel();
-        tokio::spawn(async move { tracker_loop(tracker_sender, recv).await });
+    fn init(ctx: &NodeContext<Self::Body>) -> Self {
+        let adjecents = HashMap::with_capacity(ctx.node_ids().len());
         Self {
-            id: node_id,
-            counter: MessageIdCounter,
-            sender,
             messages: HashSet::new(),
             adjecents,
-            tracker,
+            outstanding: Reliable::new(RetryPolicy {
+                initial: RESEND_TIMEOUT,
+                max_backoff: MAX_RESEND_TIMEOUT,
+                ..RetryPolicy::default()
+            }),
         }
     }
 
-    fn next_id(&mut self) -> MessageId {
-        self.counter.next_id()
-    }
-
     // What needs to happen when a broadcast message is recieved:
     //  Message value is added to our messages
     //   - If it was an unknown value, create a broadcast message for each adjacent node that doesn't
@@ -94,54 +78,77 @@ impl Node for BroadcastNode {
     //
     fn handle_msg(
         &mut self,
+        ctx: &NodeContext<Self::Body>,
         mut msg: Message<Self::Body>,
-    ) -> anyhow::Result<Option<Message<Self::Body>>> {
-        eprintln!("processing message: {msg:?}");
+    ) -> anyhow::Result<Outbox<Self::Body>> {
+        trace!(?msg, "processing message");
         match &mut msg.body {
             BroadcastBody::Broadcast { msg_id, message } => {
                 let msg_id = *msg_id;
                 let message = *message;
-                self.handle_broadcast(&msg, msg_id, message);
+                let forwards = self.handle_broadcast(ctx, msg_id, message);
                 msg.into_response(|body| {
                     *body = BroadcastBody::BroadcastOk {
-                        msg_id: self.next_id(),
+                        // The client stamps a fresh message id
+                        msg_id: MessageId::default(),
                         in_reply_to: msg_id,
                     }
                 });
-                Ok(Some(msg))
+                let mut outbox = Outbox::from(msg);
+                // The forwards are tracked by their ids, so those need to be kept
+                forwards
+                    .into_iter()
+                    .for_each(|msg| outbox.push_with_id(msg));
+                Ok(outbox)
             }
             BroadcastBody::BroadcastOk {
                 msg_id,
                 in_reply_to,
             } => {
-                self.handle_broadcast_ok(&msg.src, *in_reply_to);
-                Ok(None)
+                self.handle_broadcast_ok(&msg);
+                Ok(Outbox::new())
             }
             BroadcastBody::Read { msg_id } => {
                 let msg_id = *msg_id;
                 msg.into_response(|body| {
                     *body = BroadcastBody::ReadOk {
-                        msg_id: self.next_id(),
+                        // The client stamps a fresh message id
+                        msg_id: MessageId::default(),
                         in_reply_to: msg_id,
                         messages: self.messages.clone(),
                     }
                 });
-                Ok(Some(msg))
+                Ok(msg.into())
             }
             BroadcastBody::Topology { msg_id, topology } => {
                 let msg_id = *msg_id;
-                self.handle_topology(topology);
+                self.handle_topology(ctx.node_id(), topology);
                 msg.into_response(|body| {
                     *body = BroadcastBody::TopologyOk {
-                        msg_id: self.next_id(),
+                        // The client stamps a fresh message id
+                        msg_id: MessageId::default(),
                         in_reply_to: msg_id,
                     }
                 });
-                Ok(Some(msg))
+                Ok(msg.into())
             }
-            BroadcastBody::ReadOk { .. } | BroadcastBody::TopologyOk { .. } => Ok(None),
+            BroadcastBody::ReadOk { .. } | BroadcastBody::TopologyOk { .. } => Ok(Outbox::new()),
         }
     }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(TICK_INTERVAL)
+    }
+
+    /// Resends any broadcast messages that have been waiting too long for a `BroadcastOk`.
+    fn on_tick(&mut self, ctx: &NodeContext<Self::Body>) -> anyhow::Result<Outbox<Self::Body>> {
+        // Broadcasts have no deadline, so nothing ever expires
+        let (outbox, _) = self.outstanding.poll(ctx);
+        if !outbox.is_empty() {
+            debug!(count = outbox.len(), "resending unacknowledged broadcasts");
+        }
+        Ok(outbox)
+    }
 }
 
 impl BroadcastNode {
@@ -149,48 +156,52 @@ impl BroadcastNode {
         self.adjecents.contains_key(node)
     }
 
-    /// Start propagating message
+    /// Start propagating message. The returned messages forward the message to each adjecent node
+    /// that doesn't know about it yet.
     fn handle_broadcast(
         &mut self,
-        msg: &Message<BroadcastBody>,
+        ctx: &NodeContext<BroadcastBody>,
         msg_id: MessageId,
         message: usize,
-    ) {
+    ) -> Vec<Message<BroadcastBody>> {
         self.messages.insert(message);
-        self.adjecents
-            .iter_mut()
-            .filter_map(|(n, adj)| {
-                adj.add_message(&mut self.counter, message)
-                    .map(|body| (n.clone(), body))
-            })
-            .map(|(dest, body)| Message {
-                src: self.id.clone(),
-                dest: format!("n{}", dest.strip_prefix('n').unwrap()),
-                body,
-            })
-            .for_each(|msg| {
-                let json = serde_json::to_string(&msg).unwrap();
-                self.tracker
-                    .send(TrackerAction::Track(msg.clone()))
-                    .unwrap();
-                eprintln!("forwarding broadcast message: {json}");
-                self.sender.send(msg).unwrap();
-            })
+        let mut forwards = Vec::new();
+        for (dest, adj) in self.adjecents.iter_mut() {
+            if adj.known.contains(&message) {
+                continue;
+            }
+            let body = BroadcastBody::Broadcast {
+                // The tracker stamps a fresh message id
+                msg_id: MessageId::default(),
+                message,
+            };
+            let msg = self
+                .outstanding
+                .track(ctx, ctx.message(dest.clone(), body))
+                .expect("broadcast bodies have message ids");
+            adj.add_message(msg.body.msg_id().unwrap(), message);
+            debug!(dest = %msg.dest, message, "forwarding broadcast");
+            forwards.push(msg);
+        }
+        forwards
     }
 
     /// Confirm the message has been propagated
-    fn handle_broadcast_ok(&mut self, src: &String, msg_id: MessageId) {
-        self.tracker.send(TrackerAction::Stop(msg_id)).unwrap();
-        if let Some(adj) = self.adjecents.get_mut(src) {
-            adj.update_pending(msg_id);
+    fn handle_broadcast_ok(&mut self, msg: &Message<BroadcastBody>) {
+        if let Some(Delivery::Delivered { request, .. }) = self.outstanding.ack(msg) {
+            if let (Some(adj), Some(msg_id)) =
+                (self.adjecents.get_mut(&request.dest), request.body.msg_id())
+            {
+                adj.update_pending(msg_id);
+            }
         }
     }
 
-    fn handle_topology(&mut self, topology: &mut HashMap<String, HashSet<String>>) {
+    fn handle_topology(&mut self, id: &str, topology: &mut HashMap<String, HashSet<String>>) {
         self.adjecents.extend(
             topology
-                .remove(&self.id)
-                .expect(&format!("node {} was not in topology", self.id))
+                .remove(id)
+                .expect(&format!("node {id} was not in topology"))
                 .into_iter()
                 .map(|n| (n, Adjecent::default())),
         );
@@ -198,19 +209,9 @@ impl BroadcastNode {
 }
 
 impl Adjecent {
-    /// Attempts to add a message to the adjecent node's pending queue. Succeeds only if the
-    /// message doesn't exist in the known values. On success, a broadcast body is returned.
-    /// Otherwise, `None` is returned.
-    fn add_message(
-        &mut self,
-        counter: &mut MessageIdCounter,
-        message: usize,
-    ) -> Option<BroadcastBody> {
-        (!self.known.contains(&message)).then(|| {
-            let msg_id = counter.next_id();
-            self.pending.insert(msg_id, message);
-            BroadcastBody::Broadcast { msg_id, message }
-        })
+    /// Adds a message that has been forwarded to the adjecent node to its pending queue.
+    fn add_message(&mut self, msg_id: MessageId, message: usize) {
+        self.pending.insert(msg_id, message);
     }
 
     fn update_pending(&mut self, msg_id: MessageId) {
@@ -219,77 +220,3 @@ impl Adjecent {
             .map(|val| self.known.insert(val));
     }
 }
-
-impl MessageIdCounter {
-    fn next_id(&mut self) -> MessageId {
-        let id = MESSAGE_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
-        MessageId(id)
-    }
-}
-
-async fn tracker_loop(
-    send: UnboundedSender<Message<BroadcastBody>>,
-    mut recv: UnboundedReceiver<TrackerAction>,
-) {
-    fn handle_msg(
-        msg: TrackerAction,
-        cancellations: &mut HashSet<MessageId>,
-        trackers: &mut VecDeque<(Message<BroadcastBody>, SystemTime)>,
-    ) {
-        match msg {
-            TrackerAction::Track(msg) => match &msg.body {
-                BroadcastBody::Broadcast { .. } => {
-                    eprintln!("Tracking message: {msg:?}");
-                    trackers.push_back((msg, SystemTime::now()));
-                }
-                _ => {}
-            },
-            TrackerAction::Stop(id) => {
-                eprintln!("Cancelling tracking of message: {id:?}");
-                cancellations.insert(id);
-            }
-        }
-    }
-    let mut cancellations: HashSet<MessageId> = HashSet::new();
-    let mut trackers: VecDeque<(Message<BroadcastBody>, SystemTime)> = VecDeque::new();
-    let mut counter = MessageIdCounter;
-    loop {
-        match trackers.front_mut() {
-            None => {
-                handle_msg(
-                    recv.recv().await.unwrap(),
-                    &mut cancellations,
-                    &mut trackers,
-                );
-            }
-            Some((msg, timer)) => {
-                match &msg.body {
-                    BroadcastBody::Broadcast { msg_id, .. } => {
-                        if cancellations.remove(msg_id) {
-                            eprintln!("Cancellation found for {msg_id:?}");
-                            trackers.pop_front();
-                            continue;
-                        }
-                    }
-                    _ => panic!("Only broadcast messages should be tracked"),
-                }
-                let elapsed = timer.elapsed().unwrap().as_millis() as u64;
-                if elapsed >= 150 {
-                    eprintln!("Oldest message has been waiting for too long. Resending...");
-                    send.send(msg.clone_with_msg_id(counter.next_id()));
-                    *timer = SystemTime::now();
-                    trackers.rotate_left(1);
-                } else {
-                    tokio::select! {
-                        _ = tokio::time::sleep(Duration::from_millis(150 - elapsed)) => {
-                            eprintln!("Slept. Time to loop again.");
-                        }
-                        msg = recv.recv() => {
-                            handle_msg(msg.unwrap(), &mut cancellations, &mut trackers);
-                        }
-                    }
-                }
-            }
-        }
-    }
-}
@@ -1,45 +1,38 @@
 use aurora::*;
-use tokio::sync::mpsc::UnboundedSender;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), Error> {
+    init_logging();
     main_loop::<EchoNode>().await
 }
 
-struct EchoNode {
-    counter: usize,
-}
+struct EchoNode;
 
 impl Node for EchoNode {
     type Body = EchoBody;
 
-    fn init(_: UnboundedSender<Message<Self::Body>>, _: String, _: Vec<String>) -> Self {
-        Self { counter: 0 }
-    }
-
-    fn next_id(&mut self) -> MessageId {
-        let id = self.counter;
-        MessageId(id)
+    fn init(_: &NodeContext<Self::Body>) -> Self {
+        Self
     }
 
     fn handle_msg(
         &mut self,
+        _: &NodeContext<Self::Body>,
         mut msg: Message<Self::Body>,
-    ) -> anyhow::Result<Option<Message<Self::Body>>> {
+    ) -> anyhow::Result<Outbox<Self::Body>> {
         match &msg.body {
-            EchoBody::EchoOk { .. } => Ok(None),
-            EchoBody::Echo { msg_id: id, echo } => {
+            EchoBody::EchoOk { .. } => Ok(Outbox::new()),
+            EchoBody::Echo { echo, .. } => {
                 let echo = echo.clone();
-                let in_reply_to = *id;
-                let msg_id = self.next_id();
+                // `into_response` and the client fill in the message ids
                 msg.into_response(|body| {
                     *body = EchoBody::EchoOk {
                         echo,
-                        msg_id,
-                        in_reply_to,
+                        msg_id: MessageId::default(),
+                        in_reply_to: MessageId::default(),
                     }
                 });
-                Ok(Some(msg))
+                Ok(msg.into())
             }
         }
     }
@@ -0,0 +1,134 @@
+use aurora::{either::Either, *};
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    init_logging();
+    main_loop::<GCounterNode>().await
+}
+
+type Body = OneOf<GCounterBody, KvBody>;
+
+/// Approach:
+/// Each node keeps its own counter in `seq-kv` under its node id. Since only that node writes the
+/// key, adding is a compare-and-set loop that almost never conflicts, and because `seq-kv` orders
+/// each node's own operations, the node always sees its latest count.
+///
+/// Reads sum every node's counter. `seq-kv` can serve stale values for the other nodes' keys, so
+/// the read starts with a barrier (a unique write of our own) which brings our view of the store
+/// up to date with every add that finished before the read.
+#[derive(Debug)]
+struct GCounterNode {
+    kv: SeqKvClient<Body>,
+}
+
+impl AsyncNode for GCounterNode {
+    type Body = Body;
+
+    async fn init(ctx: &NodeContext<Self::Body>) -> Self {
+        Self {
+            kv: SeqKvClient::new(ctx),
+        }
+    }
+
+    async fn handle_msg(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> anyhow::Result<Outbox<Self::Body>> {
+        // Replies from `seq-kv` are routed to their RPCs, so only workload messages are handled
+        let Either::Left(mut msg) = msg.split() else {
+            return Ok(Outbox::new());
+        };
+        let body = match msg.body {
+            GCounterBody::Add { delta, .. } => {
+                self.kv
+                    .update(counter_key(ctx.node_id()), 0u64, |count| count + delta)
+                    .await?;
+                GCounterBody::AddOk {
+                    msg_id: MessageId::default(),
+                    in_reply_to: MessageId::default(),
+                }
+            }
+            GCounterBody::Read { .. } => {
+                self.kv.barrier().await?;
+                let mut value = 0;
+                for node in ctx.node_ids() {
+                    value += match self.kv.read::<_, u64>(counter_key(node)).await {
+                        Ok(count) => count,
+                        // Nodes that have not been added to yet have no counter
+                        Err(KvError::KeyDoesNotExist) => 0,
+                        Err(err) => return Err(err.into()),
+                    };
+                }
+                GCounterBody::ReadOk {
+                    msg_id: MessageId::default(),
+                    in_reply_to: MessageId::default(),
+                    value,
+                }
+            }
+            GCounterBody::AddOk { .. } | GCounterBody::ReadOk { .. } => return Ok(Outbox::new()),
+        };
+        // `into_response` and the client fill in the message ids
+        msg.into_response(|old| *old = body);
+        Ok(Outbox::from(msg.map_body(OneOf::left)))
+    }
+}
+
+/// The `seq-kv` key that holds a node's counter.
+fn counter_key(node: &str) -> String {
+    format!("counter/{node}")
+}
+
+#[cfg(test)]
+mod tests {
+    use aurora::*;
+    use serde_json::{json, Value};
+
+    use super::GCounterNode;
+
+    const NODES: [&str; 3] = ["n1", "n2", "n3"];
+
+    /// Waits for `count` replies and checks that they all have the given type.
+    async fn replies(client: &mut ClusterClient, kind: &str, count: u64) -> Vec<Value> {
+        let mut replies = Vec::new();
+        while (replies.len() as u64) < count {
+            let reply = client.recv().await.unwrap();
+            assert_eq!(reply["body"]["type"], kind, "unexpected reply {reply}");
+            replies.push(reply["body"].clone());
+        }
+        replies
+    }
+
+    #[tokio::test]
+    async fn concurrent_adds_are_all_counted() {
+        let mut cluster = Cluster::new(NODES.map(String::from).to_vec());
+        cluster.add::<SeqKvService>(SEQ_KV);
+        for node in NODES {
+            cluster.add::<GCounterNode>(node);
+        }
+        let mut clients: Vec<_> = (1..=3).map(|i| cluster.client(format!("c{i}"))).collect();
+
+        // Every client adds through every node without waiting for the earlier adds
+        let mut msg_id = 0;
+        let mut total = 0;
+        for client in &clients {
+            for node in NODES {
+                for delta in 1..=5u64 {
+                    msg_id += 1;
+                    total += delta;
+                    let body = json!({"type": "add", "msg_id": msg_id, "delta": delta});
+                    client.send(node, body);
+                }
+            }
+        }
+        for client in &mut clients {
+            replies(client, "add_ok", 15).await;
+        }
+
+        for (client, node) in clients.iter_mut().zip(NODES) {
+            client.send(node, json!({"type": "read", "msg_id": 1}));
+            let reply = replies(client, "read_ok", 1).await;
+            assert_eq!(reply[0]["value"], total, "{node} read the wrong total");
+        }
+    }
+}
@@ -1,48 +1,40 @@
 use aurora::*;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), Error> {
+    init_logging();
     main_loop::<IdsNode>().await
 }
 
-struct IdsNode {
-    id: String,
-    counter: usize,
-}
+struct IdsNode;
 
 impl Node for IdsNode {
     type Body = IdBody;
 
-    fn init(
-        _: tokio::sync::mpsc::UnboundedSender<Message<Self::Body>>,
-        node_id: String,
-        _: Vec<String>,
-    ) -> Self {
-        Self {
-            id: node_id,
-            counter: 0,
-        }
-    }
-
-    fn next_id(&mut self) -> MessageId {
-        let id = self.counter;
-        MessageId(id)
+    fn init(_: &NodeContext<Self::Body>) -> Self {
+        Self
     }
 
     fn handle_msg(
         &mut self,
+        ctx: &NodeContext<Self::Body>,
         mut msg: Message<Self::Body>,
-    ) -> anyhow::Result<Option<Message<Self::Body>>> {
+    ) -> anyhow::Result<Outbox<Self::Body>> {
         match &msg.body {
-            IdBody::GenerateOk { .. } => Ok(None),
-            IdBody::Generate => {
-                let id = self.next_id().0;
+            IdBody::GenerateOk { .. } => Ok(Outbox::new()),
+            IdBody::Generate { .. } => {
+                // Message ids are unique within the node, so they make unique ids when paired with
+                // its id
+                let id = ctx.next_id().0;
+                // `into_response` and the client fill in the message ids
                 msg.into_response(|body| {
                     *body = IdBody::GenerateOk {
-                        id: format!("{}-{id}", self.id),
+                        msg_id: MessageId::default(),
+                        in_reply_to: MessageId::default(),
+                        id: format!("{}-{id}", ctx.node_id()),
                     }
                 });
-                Ok(Some(msg))
+                Ok(msg.into())
             }
         }
     }
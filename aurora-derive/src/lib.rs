@@ -0,0 +1,366 @@
+//! The derive macros for the `aurora` library. These are re-exported by `aurora`, so they should be
+//! used through it rather than by depending on this crate directly.
+
+#![warn(rust_2018_idioms)]
+#![deny(missing_docs, unreachable_pub, unsafe_code)]
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Error, Expr, Fields, Ident,
+    LitStr, Type,
+};
+
+/// Derives `MessageBody`, `BodyTypes`, `Serialize`, and `Deserialize` for a body enum.
+///
+/// Bodies are written the way Maelstrom expects them. The variant is written in the `type` field,
+/// and its name is converted to snake case (e.g. `EchoOk` becomes `echo_ok`). Other names can be
+/// given with `#[serde(rename = "...")]`, and any other serde attributes on variants and fields are
+/// kept as well.
+///
+/// Variants with a `msg_id` or `in_reply_to` field of type `MessageId` or `Option<MessageId>` have
+/// those fields read and updated by the `MessageBody` methods.
+///
+/// A variant named `FooOk` is taken to be the response to the variant named `Foo`. Other pairings
+/// can be given with `#[body(response = OtherVariant)]` on the request's variant.
+#[proc_macro_derive(MessageBody, attributes(serde, body))]
+pub fn derive_message_body(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// What the derive needs to know about each variant.
+struct Variant {
+    ident: Ident,
+    /// The variant's name in the `type` field
+    wire_name: String,
+    /// The variant that responds to this one, if one was given
+    response: Option<Ident>,
+    /// The variant as it is written in the serde mirror of the enum
+    mirror: TokenStream2,
+    msg_id: Option<IdField>,
+    in_reply_to: Option<IdField>,
+}
+
+/// The types that `msg_id` and `in_reply_to` fields can have.
+#[derive(Clone, Copy)]
+enum IdField {
+    Required,
+    Optional,
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "`MessageBody` can not be derived for generic types",
+        ));
+    }
+    if let Some(attr) = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("serde"))
+    {
+        return Err(Error::new(
+            attr.span(),
+            "`MessageBody` sets the serde attributes of the enum itself",
+        ));
+    }
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new(
+            Span::call_site(),
+            "`MessageBody` can only be derived for enums",
+        ));
+    };
+    let variants = data
+        .variants
+        .iter()
+        .map(parse_variant)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mirrors = variants.iter().map(|var| &var.mirror);
+    let remote = LitStr::new(&name.to_string(), name.span());
+    let update_msg_id = update_arms(&variants, |var| var.msg_id, quote!(msg_id));
+    let update_in_reply_to = update_arms(&variants, |var| var.in_reply_to, quote!(in_reply_to));
+    let msg_id = read_arms(&variants, |var| var.msg_id, quote!(msg_id));
+    let in_reply_to = read_arms(&variants, |var| var.in_reply_to, quote!(in_reply_to));
+    let types = variants.iter().map(|var| &var.wire_name);
+    let type_arms = variants.iter().map(|var| {
+        let ident = &var.ident;
+        let wire_name = &var.wire_name;
+        quote!(Self::#ident { .. } => #wire_name,)
+    });
+    let pairs = pairs(&variants)?;
+
+    Ok(quote! {
+        const _: () = {
+            use ::aurora::__private::serde;
+
+            #[derive(serde::Serialize, serde::Deserialize)]
+            #[serde(
+                crate = "::aurora::__private::serde",
+                remote = #remote,
+                tag = "type",
+                rename_all = "snake_case"
+            )]
+            #[allow(dead_code)]
+            enum __MessageBodyMirror {
+                #(#mirrors,)*
+            }
+
+            impl serde::Serialize for #name {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> ::core::result::Result<S::Ok, S::Error> {
+                    __MessageBodyMirror::serialize(self, serializer)
+                }
+            }
+
+            impl<'de> serde::Deserialize<'de> for #name {
+                fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> ::core::result::Result<Self, D::Error> {
+                    __MessageBodyMirror::deserialize(deserializer)
+                }
+            }
+
+            impl ::aurora::MessageBody for #name {
+                fn update_msg_id(&mut self, id: ::aurora::MessageId) {
+                    #update_msg_id
+                }
+
+                fn update_in_reply_to(&mut self, id: ::aurora::MessageId) {
+                    #update_in_reply_to
+                }
+
+                fn msg_id(&self) -> ::core::option::Option<::aurora::MessageId> {
+                    #msg_id
+                }
+
+                fn in_reply_to(&self) -> ::core::option::Option<::aurora::MessageId> {
+                    #in_reply_to
+                }
+
+                fn is_reply(&self) -> bool {
+                    <Self as ::aurora::BodyTypes>::is_response(self)
+                }
+
+                fn knows_type(kind: &str) -> ::core::option::Option<bool> {
+                    ::core::option::Option::Some(
+                        <Self as ::aurora::BodyTypes>::TYPES.contains(&kind),
+                    )
+                }
+            }
+
+            impl ::aurora::BodyTypes for #name {
+                const TYPES: &'static [&'static str] = &[#(#types),*];
+                const PAIRS: &'static [(&'static str, &'static str)] = &[#(#pairs),*];
+
+                fn body_type(&self) -> &'static str {
+                    match self {
+                        #(#type_arms)*
+                    }
+                }
+            }
+        };
+    })
+}
+
+fn parse_variant(var: &syn::Variant) -> Result<Variant, Error> {
+    let attrs = serde_attrs(&var.attrs);
+    let wire_name = match renamed(&attrs)? {
+        Some(name) => name,
+        None => snake_case(&var.ident.to_string()),
+    };
+    let response = response(&var.attrs)?;
+    let ident = &var.ident;
+    let mirror = match &var.fields {
+        Fields::Named(fields) => {
+            let fields = fields.named.iter().map(|field| {
+                let field_attrs = serde_attrs(&field.attrs);
+                let name = &field.ident;
+                let ty = &field.ty;
+                quote!(#(#field_attrs)* #name: #ty)
+            });
+            quote!(#(#attrs)* #ident { #(#fields),* })
+        }
+        Fields::Unnamed(fields) => {
+            let fields = fields.unnamed.iter().map(|field| {
+                let field_attrs = serde_attrs(&field.attrs);
+                let ty = &field.ty;
+                quote!(#(#field_attrs)* #ty)
+            });
+            quote!(#(#attrs)* #ident ( #(#fields),* ))
+        }
+        Fields::Unit => quote!(#(#attrs)* #ident),
+    };
+    let find_id = |name: &str| match &var.fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .find(|field| field.ident.as_ref().is_some_and(|ident| ident == name))
+            .map(|field| id_field(&field.ty)),
+        Fields::Unnamed(_) | Fields::Unit => None,
+    };
+    Ok(Variant {
+        ident: ident.clone(),
+        wire_name,
+        response,
+        mirror,
+        msg_id: find_id("msg_id"),
+        in_reply_to: find_id("in_reply_to"),
+    })
+}
+
+/// Returns the serde attributes out of a list of attributes.
+fn serde_attrs(attrs: &[Attribute]) -> Vec<&Attribute> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("serde"))
+        .collect()
+}
+
+/// Finds the name given by `#[serde(rename = "...")]`, if there is one.
+fn renamed(attrs: &[&Attribute]) -> Result<Option<String>, Error> {
+    let mut name = None;
+    for attr in attrs {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let lit: LitStr = meta.value()?.parse()?;
+                name = Some(lit.value());
+            } else if meta.input.peek(syn::Token![=]) {
+                // Other attributes are left to serde
+                meta.value()?.parse::<Expr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                meta.parse_nested_meta(|meta| {
+                    if meta.input.peek(syn::Token![=]) {
+                        meta.value()?.parse::<Expr>()?;
+                    }
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(name)
+}
+
+/// Finds the variant given by `#[body(response = ...)]`, if there is one.
+fn response(attrs: &[Attribute]) -> Result<Option<Ident>, Error> {
+    let mut response = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("body")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("response") {
+                response = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `response`"))
+            }
+        })?;
+    }
+    Ok(response)
+}
+
+/// Works out whether a `msg_id` or `in_reply_to` field is optional. Anything that is not an
+/// `Option` is assumed to be a `MessageId`.
+fn id_field(ty: &Type) -> IdField {
+    match ty {
+        Type::Path(path)
+            if path
+                .path
+                .segments
+                .last()
+                .is_some_and(|seg| seg.ident == "Option") =>
+        {
+            IdField::Optional
+        }
+        _ => IdField::Required,
+    }
+}
+
+/// Builds the body of a method that updates one of the id fields.
+fn update_arms<F>(variants: &[Variant], field: F, name: TokenStream2) -> TokenStream2
+where
+    F: Fn(&Variant) -> Option<IdField>,
+{
+    let arms = variants.iter().filter_map(|var| {
+        let ident = &var.ident;
+        field(var).map(|kind| match kind {
+            IdField::Required => quote!(Self::#ident { #name, .. } => *#name = id,),
+            IdField::Optional => {
+                quote!(Self::#ident { #name, .. } => *#name = ::core::option::Option::Some(id),)
+            }
+        })
+    });
+    let rest = variants
+        .iter()
+        .any(|var| field(var).is_none())
+        .then(|| quote!(_ => {}));
+    quote! {
+        match self {
+            #(#arms)*
+            #rest
+        }
+    }
+}
+
+/// Builds the body of a method that reads one of the id fields.
+fn read_arms<F>(variants: &[Variant], field: F, name: TokenStream2) -> TokenStream2
+where
+    F: Fn(&Variant) -> Option<IdField>,
+{
+    let arms = variants.iter().filter_map(|var| {
+        let ident = &var.ident;
+        field(var).map(|kind| match kind {
+            IdField::Required => {
+                quote!(Self::#ident { #name, .. } => ::core::option::Option::Some(*#name),)
+            }
+            IdField::Optional => quote!(Self::#ident { #name, .. } => *#name,),
+        })
+    });
+    let rest = variants
+        .iter()
+        .any(|var| field(var).is_none())
+        .then(|| quote!(_ => ::core::option::Option::None,));
+    quote! {
+        match self {
+            #(#arms)*
+            #rest
+        }
+    }
+}
+
+/// Pairs each request with its response.
+fn pairs(variants: &[Variant]) -> Result<Vec<TokenStream2>, Error> {
+    let find = |ident: &str| variants.iter().find(|var| var.ident == ident);
+    let mut pairs = Vec::new();
+    for var in variants {
+        let response = match &var.response {
+            Some(response) => Some(find(&response.to_string()).ok_or_else(|| {
+                Error::new(
+                    response.span(),
+                    format!("there is no variant named `{response}`"),
+                )
+            })?),
+            None => find(&format!("{}Ok", var.ident)),
+        };
+        if let Some(response) = response {
+            let request = &var.wire_name;
+            let response = &response.wire_name;
+            pairs.push(quote!((#request, #response)));
+        }
+    }
+    Ok(pairs)
+}
+
+/// Converts a variant name to snake case the same way that serde does.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, ch) in name.char_indices() {
+        if i > 0 && ch.is_uppercase() {
+            snake.push('_');
+        }
+        snake.push(ch.to_ascii_lowercase());
+    }
+    snake
+}
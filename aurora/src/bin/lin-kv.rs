@@ -0,0 +1,9 @@
+//! Runs aurora's stand-in for Maelstrom's `lin-kv` service. See `LinKvService`.
+
+use aurora::*;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    init_logging();
+    main_loop::<LinKvService>().await
+}
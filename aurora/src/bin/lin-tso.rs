@@ -0,0 +1,9 @@
+//! Runs aurora's stand-in for Maelstrom's `lin-tso` service. See `LinTsoService`.
+
+use aurora::*;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    init_logging();
+    main_loop::<LinTsoService>().await
+}
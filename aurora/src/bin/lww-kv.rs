@@ -0,0 +1,10 @@
+//! Runs aurora's stand-in for Maelstrom's `lww-kv` service. See `LwwKvService`. Set
+//! `AURORA_LWW_KV_MERGE_DELAY_MS` to change how long it takes to merge writes.
+
+use aurora::*;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    init_logging();
+    main_loop::<LwwKvService>().await
+}
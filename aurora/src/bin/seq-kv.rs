@@ -0,0 +1,10 @@
+//! Runs aurora's stand-in for Maelstrom's `seq-kv` service. See `SeqKvService`. Set
+//! `AURORA_SEQ_KV_STALENESS_MS` to change how stale its reads can be.
+
+use aurora::*;
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    init_logging();
+    main_loop::<SeqKvService>().await
+}
@@ -1,158 +1,818 @@
-use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use serde_json::Value;
 use tokio::{
-    io::{AsyncBufReadExt, BufReader, Lines, Stdin},
-    sync::mpsc::{self, error::TryRecvError, UnboundedReceiver},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, BufReader, Lines, Stdin},
+    task::JoinSet,
 };
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
 
-use crate::{InitBody, Message, MessageBody, Node};
+use crate::{
+    log_message, queue, AsyncNode, ClientConfig, ConcurrentNode, DedupCache, Error, ErrorBody,
+    ErrorCode, IdAllocator, Inbound, InitBody, MalformedPolicy, Message, MessageBody, MessageId,
+    NodeContext, NodeRng, OrError, Outbound, Outbox, OutputStats, QueueReceiver, QueueStats, Seen,
+    Sender, SharedNode, ShutdownReason, TaskSet, Terminate, TimerEvent, Timers, Waiter, Writer,
+};
 
 /// The main loop for problem solutions to use. This handles creating the client and node, looping
-/// until the process is killed, and pulling, processing, and sending messages. Of course,
-/// solutions can implement whatever loop they want.
-pub async fn main_loop<N: Node>() {
-    let (mut client, mut node): (_, N) = Client::new().await;
-    let mut counter = 0;
+/// until the input is closed or the process is terminated, and pulling, processing, and sending
+/// messages. Of course, solutions can implement whatever loop they want.
+///
+/// If the node fails to handle a message, an error message is sent back to the message's sender.
+/// See `ErrorBody::from_handler_err` for how the error is constructed.
+///
+/// Once the input is closed (or SIGTERM is received), the inputs that have already arrived are
+/// handled and then the client shuts down. See `Client::shutdown` for details.
+pub async fn main_loop<N: AsyncNode>() -> Result<(), Error> {
+    main_loop_with_config::<N>(ClientConfig::default()).await
+}
+
+/// The same as `main_loop` but the client is created using the given config.
+pub async fn main_loop_with_config<N: AsyncNode>(config: ClientConfig) -> Result<(), Error> {
+    main_loop_with_transport::<N, _, _>(stdio_input(), tokio::io::stdout(), config).await
+}
+
+/// The same as `main_loop` but messages are read from the given input and written to the given
+/// output instead of stdin and stdout. This allows a node to be driven by anything that can
+/// transport lines of text, such as in-memory duplex streams, files, and sockets.
+///
+/// NOTE: Streams that are both readable and writable (e.g. `TcpStream` or `DuplexStream`) can be
+/// used by splitting them with `tokio::io::split` and wrapping the read half in a `BufReader`.
+pub async fn main_loop_with_transport<N, R, W>(
+    input: R,
+    output: W,
+    config: ClientConfig,
+) -> Result<(), Error>
+where
+    N: AsyncNode,
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (client, node): (_, N) = Client::with_transport(input, output, config).await?;
+    let span = client.span.clone();
+    run(client, node).instrument(span).await
+}
+
+/// The same as `main_loop_concurrent` but messages are read from the given input and written to
+/// the given output. See `main_loop_with_transport`.
+pub async fn main_loop_concurrent_with_transport<N, R, W>(
+    input: R,
+    output: W,
+    config: ClientConfig,
+) -> Result<(), Error>
+where
+    N: ConcurrentNode,
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (client, SharedNode(node)): (_, SharedNode<N>) =
+        Client::with_transport(input, output, config).await?;
+    let span = client.span.clone();
+    run_concurrent(client, node).instrument(span).await
+}
+
+/// Drives the node one input at a time until the client shuts down.
+async fn run<N, R>(mut client: Client<N, R>, mut node: N) -> Result<(), Error>
+where
+    N: AsyncNode,
+    R: AsyncBufRead + Unpin,
+{
+    let ctx = client.context().clone();
+    // Inputs that arrived while the node was handling another input
+    let mut backlog = VecDeque::new();
     loop {
-        let msg = match client.recv.as_mut() {
+        let input = match backlog.pop_front() {
+            Some(input) => input,
+            None if client.stopping.is_some() => break,
             None => {
-                counter += 1;
-                read_msg(&mut client.stdin).await
+                let event = client.next_event().await;
+                match client.process_event(event).await? {
+                    Some(input) => input,
+                    None => continue,
+                }
             }
-            Some(recv) => {
-                tokio::select! {
-                    msg = recv.recv() => {
-                        msg.expect("node hung up (likely crashed)")
-                    }
-                    msg = read_msg(&mut client.stdin) => {
-                        let Ok(Some(msg)) = node.handle_msg(msg) else { continue };
-                        counter += 1;
-                        msg
+        };
+        let origin = input.origin();
+        let span = input.span();
+        let handler = handle_input(&mut node, &ctx, input).instrument(span.clone());
+        tokio::pin!(handler);
+        // While the handler runs, keep the client going so that it can await RPCs
+        let res = loop {
+            let event = tokio::select! {
+                res = &mut handler => break res,
+                event = client.next_event() => event,
+            };
+            match client.process_event(event).await? {
+                // Ticks that pile up while the node is busy are merged into one
+                Some(Input::Timer(TimerEvent::Tick))
+                    if backlog.contains(&Input::Timer(TimerEvent::Tick)) => {}
+                Some(input) => backlog.push_back(input),
+                None => {}
+            }
+        };
+        client.finish_input(res, origin).instrument(span).await?;
+    }
+    if let Err(err) = node.shutdown(&ctx).await {
+        error!(error = %format!("{err:#}"), "node failed to shut down");
+    }
+    client.shutdown().await
+}
+
+/// The concurrent version of `main_loop`. Each input is handled on its own task, so a handler that
+/// is waiting on an RPC does not stop other inputs from being handled. At most
+/// `ClientConfig::max_concurrent_handlers` handlers run at once; other inputs are held until a
+/// handler finishes.
+///
+/// When the client shuts down, it waits for the running handlers to finish before calling the
+/// node's `shutdown` method.
+pub async fn main_loop_concurrent<N: ConcurrentNode>() -> Result<(), Error> {
+    main_loop_concurrent_with_config::<N>(ClientConfig::default()).await
+}
+
+/// The same as `main_loop_concurrent` but the client is created using the given config.
+pub async fn main_loop_concurrent_with_config<N: ConcurrentNode>(
+    config: ClientConfig,
+) -> Result<(), Error> {
+    main_loop_concurrent_with_transport::<N, _, _>(stdio_input(), tokio::io::stdout(), config).await
+}
+
+/// Drives the node until the client shuts down, running the handler for each input on its own
+/// task.
+async fn run_concurrent<N, R>(
+    mut client: Client<SharedNode<N>, R>,
+    node: Arc<N>,
+) -> Result<(), Error>
+where
+    N: ConcurrentNode,
+    R: AsyncBufRead + Unpin,
+{
+    // A limit of zero would mean that nothing is ever handled
+    let limit = client.config.max_concurrent_handlers.max(1);
+    let ctx = client.context().clone();
+    // Inputs that are waiting for a handler to finish
+    let mut backlog: VecDeque<Input<N::Body>> = VecDeque::new();
+    let mut handlers = JoinSet::new();
+    loop {
+        while handlers.len() < limit {
+            let Some(input) = backlog.pop_front() else {
+                break;
+            };
+            let node = node.clone();
+            let ctx = ctx.clone();
+            let span = input.span();
+            handlers.spawn(
+                async move {
+                    let origin = input.origin();
+                    let res = handle_input_concurrent(&*node, &ctx, input).await;
+                    (res, origin, Span::current())
+                }
+                .instrument(span),
+            );
+        }
+        if client.stopping.is_some() && backlog.is_empty() && handlers.is_empty() {
+            break;
+        }
+        let event = tokio::select! {
+            Some(res) = handlers.join_next() => {
+                match res {
+                    Ok((res, origin, span)) => {
+                        client.finish_input(res, origin).instrument(span).await?
                     }
+                    Err(err) => error!(error = %err, "handler task failed"),
                 }
+                continue;
             }
+            event = client.next_event() => event,
         };
-        client.send_msg(msg);
-        if counter == 100 {
-            //panic!("inspection time");
+        match client.process_event(event).await? {
+            // Ticks that pile up while the node is busy are merged into one
+            Some(Input::Timer(TimerEvent::Tick))
+                if backlog.contains(&Input::Timer(TimerEvent::Tick)) => {}
+            Some(input) => backlog.push_back(input),
+            None => {}
         }
     }
+    if let Err(err) = node.shutdown(&ctx).await {
+        error!(error = %format!("{err:#}"), "node failed to shut down");
+    }
+    client.shutdown().await
 }
 
+/// Passes an input to the matching handler of the node.
+async fn handle_input<N: AsyncNode>(
+    node: &mut N,
+    ctx: &NodeContext<N::Body>,
+    input: Input<N::Body>,
+) -> anyhow::Result<Outbox<OrError<N::Body>>> {
+    match input {
+        Input::Message(msg) => Ok(node.handle_msg(ctx, msg).await?.map_bodies(OrError::Main)),
+        Input::Unknown(msg) => Ok(node
+            .handle_unknown(ctx, msg)
+            .await?
+            .map_bodies(OrError::Unknown)),
+        Input::Timer(TimerEvent::Tick) => Ok(node.on_tick(ctx).await?.map_bodies(OrError::Main)),
+        Input::Timer(TimerEvent::Timer(token)) => {
+            Ok(node.on_timer(ctx, token).await?.map_bodies(OrError::Main))
+        }
+    }
+}
+
+/// The same as `handle_input` but for nodes whose handlers run concurrently.
+async fn handle_input_concurrent<N: ConcurrentNode>(
+    node: &N,
+    ctx: &NodeContext<N::Body>,
+    input: Input<N::Body>,
+) -> anyhow::Result<Outbox<OrError<N::Body>>> {
+    match input {
+        Input::Message(msg) => Ok(node.handle_msg(ctx, msg).await?.map_bodies(OrError::Main)),
+        Input::Unknown(msg) => Ok(node
+            .handle_unknown(ctx, msg)
+            .await?
+            .map_bodies(OrError::Unknown)),
+        Input::Timer(TimerEvent::Tick) => Ok(node.on_tick(ctx).await?.map_bodies(OrError::Main)),
+        Input::Timer(TimerEvent::Timer(token)) => {
+            Ok(node.on_timer(ctx, token).await?.map_bodies(OrError::Main))
+        }
+    }
+}
+
+/// The things that the client waits on.
+#[derive(Debug)]
+enum Event<B: MessageBody> {
+    /// A message was passed to the client through the channel
+    Outbound(Outbound<B>),
+    /// A line was read from the input
+    Inbound(Result<Message<OrError<B>>, Error>),
+    /// The node's tick or one of its timers fired
+    Timer(TimerEvent),
+    /// The process was asked to terminate
+    Terminate,
+}
+
+/// The things that the client passes to the node.
+#[derive(Debug, PartialEq)]
+enum Input<B: MessageBody> {
+    /// A message for the node to handle
+    Message(Message<B>),
+    /// A message whose body has a type that the node does not know
+    Unknown(Message<Value>),
+    /// The node's tick or one of its timers fired
+    Timer(TimerEvent),
+}
+
+/// The sender, recipient, and message id of an input, which are used to reply to it if its handler
+/// fails.
+type Origin = (String, String, Option<MessageId>);
+
 /// The main client used to receive new messages and send processed responses.
-/// Received messages can come from either stdin or from the sender half of the channel that the
-/// node receives upon construction.
-/// If the node does not use the sender, the receiver is never checked for messages.
+/// Received messages can come from either the input or from the sender half of the channel that
+/// the node reaches through its `NodeContext`. By default, the client reads from stdin and writes
+/// to stdout, but any `AsyncBufRead` and `AsyncWrite` can be used instead. Messages are written to
+/// the output by a separate task, which batches together messages that are sent in quick
+/// succession.
 ///
 /// NOTE: The node does *not* need to use the sender half of the channel. The channel is intended
 /// to messsages through the client asynchronously.
+///
+/// Replies to messages sent via `Sender::rpc` are tracked by the client. When one of these
+/// replies is read, it is routed to the waiting future rather than being returned to the node.
 #[derive(Debug)]
-pub struct Client<N: Node> {
-    stdin: Lines<BufReader<Stdin>>,
-    recv: Option<UnboundedReceiver<Message<N::Body>>>,
+pub struct Client<N: AsyncNode, R = BufReader<Stdin>> {
+    input: Lines<R>,
+    writer: Writer,
+    recv: QueueReceiver<N::Body>,
+    pending: HashMap<MessageId, Waiter<N::Body>>,
+    dedup: Option<DedupCache<N::Body>>,
+    timers: Timers,
+    tasks: TaskSet,
+    terminate: Terminate,
+    stopping: Option<ShutdownReason>,
+    received: usize,
+    ids: IdAllocator,
+    ctx: NodeContext<N::Body>,
+    span: Span,
+    config: ClientConfig,
 }
 
-const INIT_ERR_MSG: &str = "init message not given";
-const STDIN_ERR_MSG: &str = "failed to read line from stdin";
-const DE_ERR_MSG: &str = "failed to parse given message";
-const SER_ERR_MSG: &str = "failed to serialize given message";
+impl<N: AsyncNode> Client<N> {
+    /// Creates a new client, waits to receive an `Init` message, constructs the node, and then
+    /// return both the client and node.
+    pub async fn new() -> Result<(Self, N), Error> {
+        Self::with_config(ClientConfig::default()).await
+    }
 
-/// The client can receive `Init` and `InitOk` messages in addition to messages with the node's
-/// expected data. To ensure that we don't ignore malformed messages, this type helps abstract over
-/// those possiblities.
-#[derive(Deserialize, Debug, PartialEq)]
-#[serde(untagged, bound = "B: MessageBody")]
-pub enum OrInit<B: MessageBody> {
-    /// The main type that the node is expecting
-    Main(Message<B>),
-    /// An `Init` or `InitOk` message
-    Init(Message<InitBody>),
+    /// The same as `new` but the client uses the given config.
+    pub async fn with_config(config: ClientConfig) -> Result<(Self, N), Error> {
+        Self::with_transport(stdio_input(), tokio::io::stdout(), config).await
+    }
 }
 
-impl<N: Node> Client<N> {
-    /// Creates a new client, waits to receive an `Init` message, constructs the node, and then
-    /// return both the client and node.
-    pub async fn new() -> (Self, N) {
-        let mut stdin = BufReader::new(tokio::io::stdin()).lines();
-        let raw_init: String = stdin
-            .next_line()
-            .await
-            .expect(INIT_ERR_MSG)
-            .expect(STDIN_ERR_MSG);
+impl<N, R> Client<N, R>
+where
+    N: AsyncNode,
+    R: AsyncBufRead + Unpin,
+{
+    /// The same as `new` but the client reads messages from the given input and writes messages
+    /// to the given output.
+    pub async fn with_transport<W>(
+        input: R,
+        output: W,
+        config: ClientConfig,
+    ) -> Result<(Self, N), Error>
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut input = input.lines();
+        let raw_init: String = input.next_line().await?.ok_or(Error::InputClosed)?;
         let init: Message<InitBody> =
-            serde_json::from_str(&raw_init).expect("failed to parse init message");
-        let Message { src, dest, body } = init;
-        let InitBody::Init { msg_id, node_id, node_ids } = body else {
-            panic!("first message in stdout was not an init message")
+            serde_json::from_str(&raw_init).map_err(|source| Error::Malformed {
+                line: raw_init.clone(),
+                source,
+            })?;
+        let Message {
+            src, dest, body, ..
+        } = init;
+        let InitBody::Init {
+            msg_id,
+            node_id,
+            node_ids,
+        } = body
+        else {
+            return Err(Error::MissingInit(raw_init));
+        };
+        let span = info_span!("node", id = %node_id);
+        let terminate = Terminate::new(config.handle_terminate)?;
+        let tasks = TaskSet::default();
+        let ids = IdAllocator::new();
+        let (send, recv) = queue(config.outbound_capacity, config.overflow);
+        let ctx = NodeContext::new(
+            node_id,
+            node_ids,
+            Sender::new(send, tasks.clone(), ids.clone()),
+            NodeRng::new(config.rng_seed),
+            span.clone(),
+        );
+        let node = N::init(&ctx).instrument(span.clone()).await;
+        if recv.try_recv().is_some() {
+            return Err(Error::SendDuringInit);
+        }
+        let mut digest = Self {
+            input,
+            writer: Writer::new(output, span.clone()),
+            recv,
+            pending: HashMap::new(),
+            dedup: config.dedup.map(DedupCache::new),
+            timers: Timers::default(),
+            tasks,
+            terminate,
+            stopping: None,
+            received: 0,
+            ids,
+            ctx,
+            span,
+            config,
         };
-        let (send, mut recv) = mpsc::unbounded_channel();
-        let mut node = N::init(send, node_id, node_ids);
-        let recv = recv
-            .try_recv()
-            .map_err(|err| (err == TryRecvError::Empty).then_some(recv))
-            .expect_err("nodes should not send messages during construction");
-        let mut digest = Self { stdin, recv };
-        let resp = Message {
-            src: dest,
-            dest: src,
-            body: InitBody::InitOk {
-                msg_id: node.next_id(),
+        digest.timers.set_tick(node.tick_interval());
+        let resp = Message::new(
+            dest,
+            src,
+            InitBody::InitOk {
+                msg_id: digest.ids.next_id(),
                 in_reply_to: msg_id,
             },
-        };
-        digest.send_msg(resp);
-        (digest, node)
+        );
+        digest.send_msg(resp).await?;
+        Ok((digest, node))
     }
 
-    /// Waits for the next message to arrive over stdin.
+    /// Sends the messages in a handler's outbox. Each message is stamped with a fresh message id,
+    /// and messages to the sender of the input are marked as replies to it. If the handler failed
+    /// while handling a message, the error is sent back to the message's sender. If deduplication
+    /// is enabled, the replies are remembered so that they can be sent again for duplicates.
+    async fn finish_input(
+        &mut self,
+        res: anyhow::Result<Outbox<OrError<N::Body>>>,
+        origin: Option<Origin>,
+    ) -> Result<(), Error> {
+        match (res, origin) {
+            (Ok(outbox), origin) => {
+                let mut replies = Vec::new();
+                for (mut msg, keep_id) in outbox.into_parts() {
+                    if !keep_id {
+                        msg.body.update_msg_id(self.ids.next_id());
+                    }
+                    // Replies to the input are remembered for its duplicates
+                    if let (Some(_), Some((src, _, Some(msg_id)))) = (&self.dedup, &origin) {
+                        if msg.dest == *src && msg.body.in_reply_to() == Some(*msg_id) {
+                            replies.push(msg.clone());
+                        }
+                    }
+                    self.send_msg(msg).await?;
+                }
+                if let Some((src, _, msg_id)) = origin {
+                    self.remember(&src, msg_id, replies);
+                }
+                Ok(())
+            }
+            (Err(err), Some((src, dest, msg_id))) => {
+                debug!(error = %format!("{err:#}"), "handler failed, replying with an error");
+                let mut body = ErrorBody::from_handler_err(&err);
+                body.msg_id = Some(self.ids.next_id());
+                body.in_reply_to = msg_id;
+                let reply = Message::new(dest, src, body);
+                if self.dedup.is_some() {
+                    let replay = reply.clone().map_body(OrError::Error);
+                    self.remember(&reply.dest, msg_id, vec![replay]);
+                }
+                self.send_msg(reply).await
+            }
+            (Err(err), None) => {
+                warn!(error = %format!("{err:#}"), "node failed to handle a timer");
+                Ok(())
+            }
+        }
+    }
+
+    /// Waits for the next message to arrive over the input.
     ///
     /// NOTE: This method does *not* check the channel. That logic is handled by the `main_loop`.
-    pub async fn next_msg(&mut self) -> Message<N::Body> {
-        read_msg(&mut self.stdin).await
+    /// This also means that replies to outstanding RPCs are returned by this method.
+    ///
+    /// Lines that can not be parsed are handled according to the client's `MalformedPolicy`.
+    pub async fn next_msg(&mut self) -> Result<Message<OrError<N::Body>>, Error> {
+        loop {
+            match read_msg(&mut self.input, &self.pending).await {
+                Ok(msg) => return Ok(msg),
+                Err(err) => self.handle_read_err(err).await?,
+            }
+        }
+    }
+
+    /// Checks if the given message is the reply to an outstanding RPC. If it is, the message is
+    /// passed to the waiting future and `None` is returned. Otherwise, the message is returned.
+    ///
+    /// NOTE: Errors that are not replies to an RPC are dropped. So are messages with unknown body
+    /// types. If one of those is a reply to an RPC, the future gets a `not-supported` error
+    /// instead.
+    pub fn route_reply(&mut self, msg: Message<OrError<N::Body>>) -> Option<Message<N::Body>> {
+        let Message {
+            src,
+            dest,
+            body,
+            id,
+            extra,
+        } = msg;
+        let waiter = body.in_reply_to().and_then(|id| self.pending.remove(&id));
+        let body = match body {
+            OrError::Main(body) => Ok(body),
+            OrError::Error(err) => Err(err),
+            OrError::Unknown(body) => Err(ErrorBody::unknown_type(&body)),
+        };
+        let msg = body.map(|body| Message {
+            src,
+            dest,
+            body,
+            id,
+            extra,
+        });
+        match (waiter, msg) {
+            (Some(waiter), msg) => {
+                // The waiting future might have been dropped. If so, the reply is discarded.
+                let _ = waiter.reply.send(msg);
+                None
+            }
+            (None, Ok(msg)) => Some(msg),
+            (None, Err(err)) => {
+                warn!(error = %err, "received an error that is not a reply to an RPC");
+                None
+            }
+        }
     }
 
-    /// Sends a message over stdout.
-    pub fn send_msg<B>(&mut self, msg: Message<B>)
+    /// Sends a message over the output. The message is passed to the client's writer task, so it
+    /// might not have been written when this returns. If the message can not be serialized, nothing
+    /// is sent and `Error::Serialize` is returned. If the writer has stopped because of an I/O
+    /// error, that error is returned.
+    pub async fn send_msg<B>(&mut self, msg: Message<B>) -> Result<(), Error>
     where
         B: MessageBody,
     {
-        eprintln!(
-            "sending outbound JSON message: {:?}",
-            serde_json::to_string(&msg).expect(SER_ERR_MSG)
+        self.writer.write(msg).await
+    }
+
+    /// Returns a handle to the allocator that the client uses to stamp message ids. This is the
+    /// same allocator that the node's sender uses.
+    pub fn ids(&self) -> IdAllocator {
+        self.ids.clone()
+    }
+
+    /// Returns the context that the client passes to the node's methods.
+    pub fn context(&self) -> &NodeContext<N::Body> {
+        &self.ctx
+    }
+
+    /// Returns statistics about the queue of messages that the node sends through its `Sender`.
+    pub fn queue_stats(&self) -> QueueStats {
+        self.ctx.sender().queue_stats()
+    }
+
+    /// Returns how many messages the client has written to its output.
+    pub fn output_stats(&self) -> OutputStats {
+        self.writer.stats()
+    }
+
+    /// Shuts the client down. Background tasks spawned through the node's sender are cancelled,
+    /// then every message that is still in the channel is sent, the writer task finishes writing,
+    /// and finally a summary is logged.
+    ///
+    /// NOTE: Replies to RPCs that are sent during shutdown will never be routed to their futures.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        let cancelled = self.tasks.abort_all();
+        while let Some(out) = self.recv.try_recv() {
+            match out {
+                Outbound::Message(msg) | Outbound::Rpc(msg, _) => self.send_msg(msg).await?,
+                Outbound::Timer(..) => {}
+            }
+        }
+        self.writer.close().await?;
+        let stats = self.writer.stats();
+        let queue = self.queue_stats();
+        let reason = self
+            .stopping
+            .map(|reason| reason.to_string())
+            .unwrap_or_else(|| String::from("the client was shut down"));
+        info!(
+            %reason,
+            received = self.received,
+            sent = stats.messages,
+            bytes = stats.bytes,
+            flushes = stats.flushes,
+            max_queue_depth = queue.max_depth,
+            dropped = queue.dropped,
+            rejected = queue.rejected,
+            cancelled,
+            "client shut down"
         );
-        println!("{}", serde_json::to_string(&msg).expect(SER_ERR_MSG));
+        Ok(())
     }
-}
 
-/// Reads messages from stdin. Messages can either be a `InitOk` message or a message of the
-/// specified type. `InitOk` messages are ignored and `Init` messages cause panics. Otherwise, the
-/// message is returned
-async fn read_msg<B: MessageBody>(stdin: &mut Lines<BufReader<Stdin>>) -> Message<B> {
-    loop {
-        match stdin.next_line().await {
-            Err(err) => {
-                panic!("error while reading line: {err}")
-            }
-            Ok(None) => {
-                panic!("read nothing from stdin");
-            }
-            Ok(Some(line)) => {
-                let val: OrInit<B> =
-                    serde_json::from_str(&line).expect(&format!("{DE_ERR_MSG}: {line}"));
-                match val {
-                    OrInit::Main(msg) => {
-                        eprintln!("received inbound message: {msg:?}");
-                        return msg;
+    /// Applies the client's `MalformedPolicy` to an error that occurred while reading a message.
+    /// Errors that were not caused by a bad line are always returned.
+    async fn handle_read_err(&mut self, err: Error) -> Result<(), Error> {
+        if !err.is_malformed() {
+            return Err(err);
+        }
+        match self.config.malformed {
+            MalformedPolicy::Abort => Err(err),
+            MalformedPolicy::Skip => {
+                warn!(error = %err, "skipping inbound line");
+                Ok(())
+            }
+            MalformedPolicy::Reply => {
+                warn!(error = %err, "replying to malformed inbound line");
+                match err.line().and_then(|line| malformed_reply(line, &err)) {
+                    Some(mut reply) => {
+                        reply.body.msg_id = Some(self.ids.next_id());
+                        self.send_msg(reply).await
                     }
-                    OrInit::Init(Message {
-                        body: InitBody::InitOk { .. },
-                        ..
-                    }) => continue,
-                    OrInit::Init(Message {
-                        body: InitBody::Init { .. },
-                        ..
-                    }) => panic!("mutliple init messages recieved"),
+                    None => Ok(()),
                 }
             }
         }
     }
+
+    /// Waits for a message from either the channel or the input, for a timer to fire, or for the
+    /// process to be terminated. Once the client is stopping, the input is no longer read. This is
+    /// cancellation safe.
+    async fn next_event(&mut self) -> Event<N::Body> {
+        let running = self.stopping.is_none();
+        tokio::select! {
+            out = self.recv.recv() => Event::Outbound(out),
+            msg = read_msg(&mut self.input, &self.pending), if running => Event::Inbound(msg),
+            timer = self.timers.next() => Event::Timer(timer),
+            _ = self.terminate.recv(), if running => Event::Terminate,
+        }
+    }
+
+    /// Processes an event. If the event needs to be passed to the node, it is returned.
+    async fn process_event(
+        &mut self,
+        event: Event<N::Body>,
+    ) -> Result<Option<Input<N::Body>>, Error> {
+        match event {
+            Event::Outbound(out) => self.send_outbound(out).await?,
+            Event::Inbound(Ok(msg)) => {
+                self.received += 1;
+                let input = self.route_inbound(msg);
+                return self.deduplicate(input).await;
+            }
+            Event::Inbound(Err(Error::InputClosed)) => self.stop(ShutdownReason::InputClosed),
+            Event::Inbound(Err(err)) => self.handle_read_err(err).await?,
+            Event::Timer(timer) => return Ok(Some(Input::Timer(timer))),
+            Event::Terminate => self.stop(ShutdownReason::Terminated),
+        }
+        Ok(None)
+    }
+
+    /// Starts shutting the client down. The input is no longer read, so no more replies can
+    /// arrive. The pending RPCs are dropped, which resolves their futures with
+    /// `RpcError::Disconnected` rather than leaving their handlers waiting forever.
+    fn stop(&mut self, reason: ShutdownReason) {
+        self.stopping = Some(reason);
+        self.pending.clear();
+    }
+
+    /// Routes a message that was read from the input. Messages with unknown body types are passed
+    /// to the node's `handle_unknown` method, unless they are replies to an RPC.
+    fn route_inbound(&mut self, msg: Message<OrError<N::Body>>) -> Option<Input<N::Body>> {
+        let is_reply = |body: &Value| {
+            body.in_reply_to()
+                .is_some_and(|id| self.pending.contains_key(&id))
+        };
+        match msg.body {
+            OrError::Unknown(body) if !is_reply(&body) => Some(Input::Unknown(Message {
+                src: msg.src,
+                dest: msg.dest,
+                body,
+                id: msg.id,
+                extra: msg.extra,
+            })),
+            body => self
+                .route_reply(Message { body, ..msg })
+                .map(Input::Message),
+        }
+    }
+
+    /// Filters out the messages that have already been passed to the node, if deduplication is
+    /// enabled. The replies to the messages that have already been handled are sent again.
+    async fn deduplicate(
+        &mut self,
+        input: Option<Input<N::Body>>,
+    ) -> Result<Option<Input<N::Body>>, Error> {
+        let (Some(dedup), Some((src, _, Some(msg_id)))) =
+            (&mut self.dedup, input.as_ref().and_then(Input::origin))
+        else {
+            return Ok(input);
+        };
+        // Only requests are remembered. Duplicate replies are not answered, so there is nothing
+        // to replay for them.
+        if input.as_ref().is_some_and(Input::is_reply) {
+            return Ok(input);
+        }
+        match dedup.check(&src, msg_id) {
+            None => Ok(input),
+            Some(Seen::Pending(_)) => {
+                debug!(%src, ?msg_id, "dropping a duplicate of a message that is being handled");
+                Ok(None)
+            }
+            Some(Seen::Replied(replies)) => {
+                debug!(%src, ?msg_id, "replaying the replies to a duplicate message");
+                for mut reply in replies {
+                    reply.body.update_msg_id(self.ids.next_id());
+                    self.send_msg(reply).await?;
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Remembers the replies to a message, if deduplication is enabled.
+    fn remember(
+        &mut self,
+        src: &str,
+        msg_id: Option<MessageId>,
+        replies: Vec<Message<OrError<N::Body>>>,
+    ) {
+        if let (Some(dedup), Some(msg_id)) = (&mut self.dedup, msg_id) {
+            dedup.record(src, msg_id, replies);
+        }
+    }
+
+    /// Sends a message that was passed to the client through the channel. If the message is an
+    /// RPC, the client starts tracking its reply. If deduplication is enabled, replies to messages
+    /// that are remembered are recorded so that they can be sent again for duplicates.
+    async fn send_outbound(&mut self, out: Outbound<N::Body>) -> Result<(), Error> {
+        match out {
+            Outbound::Message(msg) => {
+                if let Some(dedup) = &mut self.dedup {
+                    dedup.record_sent(&msg);
+                }
+                self.send_msg(msg).await
+            }
+            Outbound::Rpc(msg, waiter) => {
+                // Once the client is stopping, replies can not be read, so the waiter is dropped
+                if let Some(id) = msg.body.msg_id().filter(|_| self.stopping.is_none()) {
+                    // Clear out any RPCs whose futures have been dropped
+                    self.pending.retain(|_, waiter| !waiter.reply.is_closed());
+                    self.pending.insert(id, waiter);
+                }
+                self.send_msg(msg).await
+            }
+            Outbound::Timer(at, token) => {
+                self.timers.schedule(at, token);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<B: MessageBody> Input<B> {
+    /// Returns the origin of the input. Only messages can be replied to if the node fails.
+    fn origin(&self) -> Option<Origin> {
+        match self {
+            Input::Message(msg) => Some((msg.src.clone(), msg.dest.clone(), msg.body.msg_id())),
+            Input::Unknown(msg) => Some((msg.src.clone(), msg.dest.clone(), msg.body.msg_id())),
+            Input::Timer(TimerEvent::Tick | TimerEvent::Timer(_)) => None,
+        }
+    }
+
+    /// Returns whether the input is a reply to some request.
+    fn is_reply(&self) -> bool {
+        match self {
+            Input::Message(msg) => msg.body.is_reply(),
+            Input::Unknown(msg) => msg.body.is_reply(),
+            Input::Timer(_) => false,
+        }
+    }
+
+    /// Returns the span that the input is handled in. Everything that the node logs while handling
+    /// a message is tagged with the message's sender and id.
+    fn span(&self) -> Span {
+        match self {
+            Input::Message(msg) => message_span(&msg.src, msg.body.msg_id()),
+            Input::Unknown(msg) => message_span(&msg.src, msg.body.msg_id()),
+            Input::Timer(TimerEvent::Tick) => info_span!("tick"),
+            Input::Timer(TimerEvent::Timer(token)) => info_span!("timer", token = token.0),
+        }
+    }
+}
+
+/// Creates the span that a message from `src` is handled in.
+fn message_span(src: &str, msg_id: Option<MessageId>) -> Span {
+    info_span!("message", %src, msg_id = msg_id.map(|id| id.0))
+}
+
+/// Creates the default input for clients, a buffered stdin.
+fn stdio_input() -> BufReader<Stdin> {
+    BufReader::new(tokio::io::stdin())
+}
+
+/// Reads messages from the input. Messages can either be a `InitOk` message, an error, or a
+/// message of the specified type. `InitOk` messages are ignored and `Init` messages cause errors.
+/// Otherwise, the message is returned. Replies to pending RPCs are read the way their waiters ask.
+async fn read_msg<B, R>(
+    input: &mut Lines<R>,
+    pending: &HashMap<MessageId, Waiter<B>>,
+) -> Result<Message<OrError<B>>, Error>
+where
+    B: MessageBody,
+    R: AsyncBufRead + Unpin,
+{
+    loop {
+        let line = input.next_line().await?.ok_or(Error::InputClosed)?;
+        let parse_reply = |id| pending.get(&id).and_then(|waiter: &Waiter<B>| waiter.parse);
+        let val = match Inbound::<B>::from_line_with(&line, parse_reply) {
+            Ok(val) => val,
+            Err(source) => return Err(Error::Malformed { line, source }),
+        };
+        match val {
+            Inbound::Main(msg) => {
+                log_message(line.as_bytes(), "received message");
+                return Ok(msg);
+            }
+            Inbound::Init(Message {
+                body: InitBody::InitOk { .. },
+                ..
+            }) => continue,
+            Inbound::Init(Message {
+                body: InitBody::Init { .. },
+                ..
+            }) => return Err(Error::DuplicateInit(line)),
+        }
+    }
+}
+
+/// Constructs a `malformed-request` error for a line that could not be parsed. The line is
+/// inspected for the sender and message id. If the sender can not be determined, no reply is
+/// constructed.
+fn malformed_reply(line: &str, err: &Error) -> Option<Message<ErrorBody>> {
+    let val: Value = serde_json::from_str(line).ok()?;
+    let src = val.get("src")?.as_str()?.to_owned();
+    let dest = val.get("dest")?.as_str()?.to_owned();
+    let in_reply_to = val
+        .pointer("/body/msg_id")
+        .and_then(Value::as_u64)
+        .map(|id| MessageId(id as usize));
+    // The line itself is left out of the reply's text since the sender already has it
+    let text = match err {
+        Error::Malformed { source, .. } => format!("failed to parse message: {source}"),
+        Error::DuplicateInit(_) => String::from("node has already been initialized"),
+        err => err.to_string(),
+    };
+    let mut body = ErrorBody::new(ErrorCode::MalformedRequest, text);
+    body.in_reply_to = in_reply_to;
+    Some(Message::new(dest, src, body))
 }
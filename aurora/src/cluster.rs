@@ -0,0 +1,163 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{json, Value};
+use tokio::{
+    io::{split, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::mpsc,
+    task::JoinSet,
+};
+use tracing::{debug, warn};
+
+use crate::{main_loop_with_transport, AsyncNode, ClientConfig};
+
+/// Where the lines for each node and client go.
+type Routes = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<String>>>>;
+
+/// Runs nodes in one process and routes messages between them, so that nodes (and the services
+/// they use) can be tested without Maelstrom.
+///
+/// Each node is driven by `main_loop_with_transport` over in-memory streams and is sent an `init`
+/// message when it is added. The lines that nodes write are routed to the node or client whose id
+/// is in their `dest` field. Messages for ids that nothing is registered for are dropped. Dropping
+/// the cluster stops every node.
+#[derive(Debug)]
+pub struct Cluster {
+    node_ids: Vec<String>,
+    routes: Routes,
+    tasks: JoinSet<()>,
+}
+
+/// A client of a `Cluster`, which plays the part of Maelstrom's clients (e.g. `c1`). Messages
+/// are written and read as JSON.
+#[derive(Debug)]
+pub struct ClusterClient {
+    id: String,
+    routes: Routes,
+    inbox: mpsc::UnboundedReceiver<String>,
+}
+
+impl Cluster {
+    /// Creates an empty cluster. The given ids are sent to every node as the cluster's node ids.
+    pub fn new(node_ids: Vec<String>) -> Self {
+        Self {
+            node_ids,
+            routes: Routes::default(),
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Starts a node with the given id. Services use their name as their id (e.g. `LIN_KV`).
+    pub fn add<N: AsyncNode + 'static>(&mut self, id: impl Into<String>) {
+        self.add_with_config::<N>(id, ClientConfig::default())
+    }
+
+    /// The same as `Cluster::add` but the node's client is created using the given config.
+    pub fn add_with_config<N: AsyncNode + 'static>(
+        &mut self,
+        id: impl Into<String>,
+        config: ClientConfig,
+    ) {
+        let id = id.into();
+        let (ours, theirs) = tokio::io::duplex(1 << 16);
+        let (node_input, node_output) = split(theirs);
+        let (output, mut input) = split(ours);
+        let (send, mut recv) = mpsc::unbounded_channel::<String>();
+        let init = json!({
+            "src": "c0",
+            "dest": id,
+            "body": {"type": "init", "msg_id": 0, "node_id": id, "node_ids": self.node_ids},
+        });
+        // The channel is unbounded, so this can only fail once the receiver is gone
+        let _ = send.send(init.to_string());
+        self.routes.lock().unwrap().insert(id.clone(), send);
+
+        let node_id = id.clone();
+        self.tasks.spawn(async move {
+            let input = BufReader::new(node_input);
+            if let Err(err) = main_loop_with_transport::<N, _, _>(input, node_output, config).await
+            {
+                warn!(node = %node_id, error = %err, "cluster node stopped");
+            }
+        });
+        self.tasks.spawn(async move {
+            while let Some(line) = recv.recv().await {
+                if input.write_all(line.as_bytes()).await.is_err()
+                    || input.write_all(b"\n").await.is_err()
+                {
+                    break;
+                }
+            }
+        });
+        let routes = self.routes.clone();
+        self.tasks.spawn(async move {
+            let mut lines = BufReader::new(output).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                route(&routes, line);
+            }
+        });
+    }
+
+    /// Creates a client with the given id. Messages sent to that id are delivered to the client.
+    pub fn client(&self, id: impl Into<String>) -> ClusterClient {
+        let id = id.into();
+        let (send, inbox) = mpsc::unbounded_channel();
+        self.routes.lock().unwrap().insert(id.clone(), send);
+        ClusterClient {
+            id,
+            routes: self.routes.clone(),
+            inbox,
+        }
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        // Lets clients know that nothing else is coming
+        self.routes.lock().unwrap().clear();
+    }
+}
+
+impl ClusterClient {
+    /// Returns the client's id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Sends a message with the given body to a node.
+    pub fn send(&self, dest: &str, body: Value) {
+        let msg = json!({"src": self.id, "dest": dest, "body": body});
+        route(&self.routes, msg.to_string());
+    }
+
+    /// Waits for the next message sent to this client. Lines that are not JSON are logged and
+    /// skipped, so this only returns `None` once the cluster is dropped.
+    pub async fn recv(&mut self) -> Option<Value> {
+        loop {
+            let line = self.inbox.recv().await?;
+            match serde_json::from_str(&line) {
+                Ok(msg) => return Some(msg),
+                Err(err) => warn!(%line, error = %err, "cluster client skipped a line"),
+            }
+        }
+    }
+}
+
+/// Sends a line to the node or client that it is addressed to.
+fn route(routes: &Routes, line: String) {
+    let dest = serde_json::from_str::<Value>(&line)
+        .ok()
+        .and_then(|msg| msg.get("dest")?.as_str().map(str::to_owned));
+    let Some(dest) = dest else {
+        warn!(%line, "cluster dropped a line without a destination");
+        return;
+    };
+    match routes.lock().unwrap().get(&dest) {
+        Some(send) => {
+            let _ = send.send(line);
+        }
+        None => debug!(%dest, "cluster dropped a message for an unknown destination"),
+    }
+}
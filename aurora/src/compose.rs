@@ -0,0 +1,217 @@
+use std::marker::PhantomData;
+
+use either::Either;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::RawValue;
+
+use crate::{parse_as, Message, MessageBody, MessageId, ParseReply, Tag};
+
+/// A body that is one of two other body types. This lets a node speak several protocols at once
+/// (e.g. a workload's body, a KV service's body, and a private gossip body) without folding all of
+/// them into one enum. More than two bodies can be composed by nesting, like
+/// `OneOf<A, OneOf<B, C>>`.
+///
+/// On the wire, the body is whichever of the two it holds; nothing is added to mark which one it
+/// is. When a body is read, it is parsed by whichever side knows its type. If both know the type,
+/// `L` is tried first and `R` is tried if the body does not fit `L`'s variant. A type that neither
+/// knows is reported as an unknown variant, so the client still hands it to the node's
+/// `handle_unknown` method.
+///
+/// Incoming messages can be routed to per-body handlers with `Message::split`, and the outboxes
+/// of those handlers can be converted back with `Outbox::map_bodies`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OneOf<L, R>(pub Either<L, R>);
+
+/// Body types that can hold another body type. Clients for Maelstrom's services (like `KvClient`)
+/// use this to send their requests as the node's body type and to pick their replies back out.
+///
+/// Every body type holds itself, and a `OneOf` holds whatever either of its sides holds, so a node
+/// can use several services at once (e.g. `OneOf<OneOf<Workload, KvBody>, TsoBody>` holds both
+/// `KvBody` and `TsoBody`). `I` says where the held body is (see `Here`, `InLeft`, and `InRight`).
+/// It is inferred, so it only needs to be named in generic bounds. A body type that holds the same
+/// body in more than one place can not be used this way, since the place is ambiguous.
+///
+/// Workloads and services can share body types (e.g. `read_ok`). Clients send their requests with
+/// `Sender::rpc_as`, so the service's replies are read as the service's body no matter which other
+/// bodies know their types.
+pub trait Holds<T: MessageBody, I>: MessageBody {
+    /// Wraps the held body type in this one.
+    fn wrap(body: T) -> Self;
+
+    /// Takes the held body type out of this one, if it holds one.
+    fn unwrap(self) -> Option<T>;
+}
+
+/// The place of a body type that is held by itself (see `Holds`).
+#[derive(Debug)]
+pub enum Here {}
+
+/// The place of a body type that is held at `I` in the left side of a `OneOf` (see `Holds`).
+#[derive(Debug)]
+pub struct InLeft<I>(PhantomData<I>);
+
+/// The place of a body type that is held at `I` in the right side of a `OneOf` (see `Holds`).
+#[derive(Debug)]
+pub struct InRight<I>(PhantomData<I>);
+
+impl<T: MessageBody> Holds<T, Here> for T {
+    fn wrap(body: T) -> Self {
+        body
+    }
+
+    fn unwrap(self) -> Option<T> {
+        Some(self)
+    }
+}
+
+impl<T: MessageBody, I, L: Holds<T, I>, R: MessageBody> Holds<T, InLeft<I>> for OneOf<L, R> {
+    fn wrap(body: T) -> Self {
+        OneOf::left(L::wrap(body))
+    }
+
+    fn unwrap(self) -> Option<T> {
+        self.into_inner().left().and_then(L::unwrap)
+    }
+}
+
+impl<T: MessageBody, I, L: MessageBody, R: Holds<T, I>> Holds<T, InRight<I>> for OneOf<L, R> {
+    fn wrap(body: T) -> Self {
+        OneOf::right(R::wrap(body))
+    }
+
+    fn unwrap(self) -> Option<T> {
+        self.into_inner().right().and_then(R::unwrap)
+    }
+}
+
+/// How a body type holds a `T`, captured when a service's client is created so that the client's
+/// type does not need to name where the `T` is.
+#[derive(Debug, Clone)]
+pub(crate) struct Held<B, T> {
+    pub(crate) wrap: fn(T) -> B,
+    pub(crate) unwrap: fn(B) -> Option<T>,
+    /// Reads a reply as a `T`, for `Sender::rpc_with`
+    pub(crate) parse: ParseReply<B>,
+}
+
+impl<B: MessageBody, T: MessageBody> Held<B, T> {
+    pub(crate) fn new<I>() -> Self
+    where
+        B: Holds<T, I>,
+    {
+        Self {
+            wrap: B::wrap,
+            unwrap: B::unwrap,
+            parse: parse_as::<T, B, I>,
+        }
+    }
+}
+
+impl<L, R> OneOf<L, R> {
+    /// Creates a body that holds the left body type.
+    pub fn left(body: L) -> Self {
+        Self(Either::Left(body))
+    }
+
+    /// Creates a body that holds the right body type.
+    pub fn right(body: R) -> Self {
+        Self(Either::Right(body))
+    }
+
+    /// Returns the underlying `Either`.
+    pub fn into_inner(self) -> Either<L, R> {
+        self.0
+    }
+}
+
+impl<L, R> From<Either<L, R>> for OneOf<L, R> {
+    fn from(body: Either<L, R>) -> Self {
+        Self(body)
+    }
+}
+
+impl<L: MessageBody, R: MessageBody> Message<OneOf<L, R>> {
+    /// Splits a message into a message of whichever body type it holds, keeping the envelope.
+    pub fn split(self) -> Either<Message<L>, Message<R>> {
+        let Message {
+            src,
+            dest,
+            body,
+            id,
+            extra,
+        } = self;
+        match body.0 {
+            Either::Left(body) => Either::Left(Message {
+                src,
+                dest,
+                body,
+                id,
+                extra,
+            }),
+            Either::Right(body) => Either::Right(Message {
+                src,
+                dest,
+                body,
+                id,
+                extra,
+            }),
+        }
+    }
+}
+
+impl<L: MessageBody, R: MessageBody> Serialize for OneOf<L, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        either::serde_untagged::serialize(&self.0, serializer)
+    }
+}
+
+/// Bodies are not deserialized as an untagged `Either` since the errors for those do not say which
+/// field could not be parsed or whether the body's type was simply unknown. Instead, the body is
+/// kept as raw text and only parsed by the sides that might know its type.
+impl<'de, L: MessageBody, R: MessageBody> Deserialize<'de> for OneOf<L, R> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let body = Box::<RawValue>::deserialize(deserializer)?;
+        let Tag { kind, .. } = serde_json::from_str(body.get()).map_err(D::Error::custom)?;
+        let left = || serde_json::from_str(body.get()).map(Self::left);
+        let right = || serde_json::from_str(body.get()).map(Self::right);
+        let res = match (L::knows_type(&kind), R::knows_type(&kind)) {
+            (Some(false), _) => right(),
+            (_, Some(false)) => left(),
+            // If only `R` is sure that it knows the type, its error says more about what is wrong
+            // with the body
+            (None, Some(true)) => left().or_else(|_| right()),
+            _ => left().or_else(|err| right().map_err(|_| err)),
+        };
+        res.map_err(D::Error::custom)
+    }
+}
+
+impl<L: MessageBody, R: MessageBody> MessageBody for OneOf<L, R> {
+    fn update_msg_id(&mut self, id: MessageId) {
+        either::for_both!(&mut self.0, body => body.update_msg_id(id))
+    }
+
+    fn update_in_reply_to(&mut self, id: MessageId) {
+        either::for_both!(&mut self.0, body => body.update_in_reply_to(id))
+    }
+
+    fn msg_id(&self) -> Option<MessageId> {
+        either::for_both!(&self.0, body => body.msg_id())
+    }
+
+    fn in_reply_to(&self) -> Option<MessageId> {
+        either::for_both!(&self.0, body => body.in_reply_to())
+    }
+
+    fn is_reply(&self) -> bool {
+        either::for_both!(&self.0, body => body.is_reply())
+    }
+
+    fn knows_type(kind: &str) -> Option<bool> {
+        match (L::knows_type(kind), R::knows_type(kind)) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+/// The settings used by the client while running a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// What the client does when an inbound line can not be parsed
+    pub malformed: MalformedPolicy,
+    /// The maximum number of handlers that can run at once. This is only used by
+    /// `main_loop_concurrent`; other inputs wait until a running handler finishes.
+    pub max_concurrent_handlers: usize,
+    /// The seed for the node's random number generator (see `NodeContext::rng`). By default, the
+    /// generator is seeded from the OS, so runs are not reproducible.
+    pub rng_seed: Option<u64>,
+    /// The maximum number of messages that can wait in the queue between the node's `Sender` and
+    /// the client. By default, the queue is unbounded.
+    pub outbound_capacity: Option<usize>,
+    /// What happens to messages that are sent while the outbound queue is full
+    pub overflow: OverflowPolicy,
+    /// Whether, and for how long, the client remembers messages so that duplicates are not passed
+    /// to the node. By default, every message is passed to the node.
+    pub dedup: Option<DedupConfig>,
+    /// Whether the client shuts down gracefully when the process is asked to terminate (SIGTERM on
+    /// Unix and Ctrl-C elsewhere). When this is off, the signal is left to whatever else handles
+    /// it.
+    pub handle_terminate: bool,
+}
+
+/// What the client does when it reads an inbound line that it can not parse.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MalformedPolicy {
+    /// Reply to the sender with a `malformed-request` error, if the sender can be determined, and
+    /// keep running
+    #[default]
+    Reply,
+    /// Log the line and keep running
+    Skip,
+    /// Stop the client and return the error
+    Abort,
+}
+
+/// What happens to a message that a node sends while the outbound queue is full. See
+/// `ClientConfig::outbound_capacity`.
+///
+/// `Sender::send_wait` is the only way of sending that can wait, so under `OverflowPolicy::Await`
+/// the other ways of sending fail like they do under `OverflowPolicy::Error`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for room in the queue. Only `Sender::send_wait` waits; the other ways of sending fail
+    /// with `RpcError::Full`
+    Await,
+    /// Drop the oldest queued message to make room for the new one. Queued RPCs are never dropped,
+    /// since their futures are waiting on them, so if only RPCs are queued the new message fails
+    /// with `RpcError::Full`
+    DropOldest,
+    /// Fail with `RpcError::Full`
+    #[default]
+    Error,
+}
+
+/// The settings for the client's deduplication layer. When it is enabled, the client remembers the
+/// requests that it passes to the node by their sender and message id. If one of them arrives
+/// again, it is not passed to the node. Instead, the replies that the node sent for the first copy
+/// (whether in the handler's outbox or through the `Sender`) are sent again with fresh message
+/// ids. Duplicates that arrive while the first copy is still being handled are dropped, since the
+/// reply to the first copy answers them as well.
+///
+/// Replies are never remembered (see `MessageBody::is_reply`), so duplicate replies are passed to
+/// the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DedupConfig {
+    /// The most messages that are remembered at once. Once there are this many, the oldest is
+    /// forgotten to make room for the next one.
+    pub capacity: usize,
+    /// How long each message is remembered for
+    pub ttl: Duration,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            malformed: MalformedPolicy::default(),
+            max_concurrent_handlers: 64,
+            rng_seed: None,
+            outbound_capacity: None,
+            overflow: OverflowPolicy::default(),
+            dedup: None,
+            handle_terminate: true,
+        }
+    }
+}
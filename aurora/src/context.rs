@@ -0,0 +1,176 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rand::{rngs::SmallRng, RngCore, SeedableRng};
+use tokio::time::Instant;
+use tracing::Span;
+
+use crate::{
+    queue, IdAllocator, Message, MessageBody, MessageId, OverflowPolicy, RpcError, Sender, TaskSet,
+    TimerToken,
+};
+
+/// The handle to the runtime that the client passes to every one of the node's methods. Through
+/// it, nodes can find out who they are and who else is in the cluster, send messages, allocate
+/// message ids, read the clock, schedule timers, generate random numbers, and log.
+///
+/// Contexts are cheap to clone, so they can be moved into background tasks.
+#[derive(Debug)]
+pub struct NodeContext<B: MessageBody> {
+    inner: Arc<Inner<B>>,
+}
+
+#[derive(Debug)]
+struct Inner<B: MessageBody> {
+    node_id: String,
+    node_ids: Vec<String>,
+    sender: Sender<B>,
+    rng: NodeRng,
+    span: Span,
+}
+
+/// The random number generator that is shared by a node's context. Every clone uses the same
+/// generator, so a node that is given a seed (see `ClientConfig::rng_seed`) behaves the same way
+/// every time it is run with the same inputs.
+///
+/// This implements `rand::RngCore`, so all of the methods from `rand::Rng` can be used with it.
+#[derive(Debug, Clone)]
+pub struct NodeRng {
+    rng: Arc<Mutex<SmallRng>>,
+}
+
+impl<B: MessageBody> NodeContext<B> {
+    pub(crate) fn new(
+        node_id: String,
+        node_ids: Vec<String>,
+        sender: Sender<B>,
+        rng: NodeRng,
+        span: Span,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                node_id,
+                node_ids,
+                sender,
+                rng,
+                span,
+            }),
+        }
+    }
+
+    /// Creates a context that is not connected to a client, which is useful for calling a node's
+    /// handlers directly in tests. Anything sent through its sender is dropped, so sending fails
+    /// with `RpcError::Disconnected`.
+    pub fn detached(node_id: impl Into<String>, node_ids: Vec<String>) -> Self {
+        let (send, _) = queue(None, OverflowPolicy::default());
+        let sender = Sender::new(send, TaskSet::default(), IdAllocator::new());
+        Self::new(
+            node_id.into(),
+            node_ids,
+            sender,
+            NodeRng::new(None),
+            Span::none(),
+        )
+    }
+
+    /// Returns the id of this node.
+    pub fn node_id(&self) -> &str {
+        &self.inner.node_id
+    }
+
+    /// Returns the ids of every node in the cluster, including this one.
+    pub fn node_ids(&self) -> &[String] {
+        &self.inner.node_ids
+    }
+
+    /// Returns the ids of every other node in the cluster.
+    pub fn peers(&self) -> impl Iterator<Item = &str> {
+        self.node_ids()
+            .iter()
+            .map(String::as_str)
+            .filter(move |id| *id != self.node_id())
+    }
+
+    /// Returns the sender, which is used to send messages outside of a handler's outbox.
+    pub fn sender(&self) -> &Sender<B> {
+        &self.inner.sender
+    }
+
+    /// Creates a message from this node to the given destination.
+    pub fn message(&self, dest: impl Into<String>, body: B) -> Message<B> {
+        Message::new(self.inner.node_id.clone(), dest, body)
+    }
+
+    /// Returns the next message id from the client's allocator.
+    pub fn next_id(&self) -> MessageId {
+        self.inner.sender.next_id()
+    }
+
+    /// Returns a handle to the client's id allocator.
+    pub fn ids(&self) -> IdAllocator {
+        self.inner.sender.ids()
+    }
+
+    /// Returns the current time according to the runtime's clock. This is tokio's clock, so it can
+    /// be paused and advanced in tests.
+    pub fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Schedules a one-shot timer. See `Sender::schedule`.
+    pub fn schedule(&self, after: Duration, token: TimerToken) -> Result<(), RpcError> {
+        self.inner.sender.schedule(after, token)
+    }
+
+    /// Returns a handle to the node's random number generator.
+    pub fn rng(&self) -> NodeRng {
+        self.inner.rng.clone()
+    }
+
+    /// Returns the node's span. Every handler already runs inside of it, but tasks that are not
+    /// spawned through the sender can use it to keep their logs tied to the node.
+    pub fn span(&self) -> &Span {
+        &self.inner.span
+    }
+}
+
+impl<B: MessageBody> Clone for NodeContext<B> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl NodeRng {
+    /// Creates a generator from the given seed or, if there is no seed, from the OS's entropy.
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_entropy(),
+        };
+        Self {
+            rng: Arc::new(Mutex::new(rng)),
+        }
+    }
+}
+
+impl RngCore for NodeRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.lock().unwrap().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.lock().unwrap().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.lock().unwrap().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.lock().unwrap().try_fill_bytes(dest)
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, VecDeque};
+
+use tokio::time::Instant;
+
+use crate::{DedupConfig, Message, MessageBody, MessageId, OrError};
+
+/// What the client remembers about a message that it passed to the node.
+#[derive(Debug, Clone)]
+pub(crate) enum Seen<B: MessageBody> {
+    /// The node is still handling the message, and these are the replies that it has sent through
+    /// its `Sender` so far
+    Pending(Vec<Message<OrError<B>>>),
+    /// The node has handled the message and these are the replies that it returned
+    Replied(Vec<Message<OrError<B>>>),
+}
+
+/// The messages that the client has passed to the node, keyed by their sender and message id. The
+/// cache is bounded by both size and age (see `DedupConfig`).
+#[derive(Debug)]
+pub(crate) struct DedupCache<B: MessageBody> {
+    config: DedupConfig,
+    seen: HashMap<(String, MessageId), Seen<B>>,
+    /// The keys of `seen` in the order that they were inserted, along with when they expire
+    order: VecDeque<(Instant, (String, MessageId))>,
+}
+
+impl<B: MessageBody> DedupCache<B> {
+    pub(crate) fn new(config: DedupConfig) -> Self {
+        Self {
+            config,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Checks whether a message has been seen before. If it has not, it is remembered as pending
+    /// and `None` is returned.
+    pub(crate) fn check(&mut self, src: &str, msg_id: MessageId) -> Option<Seen<B>> {
+        self.expire();
+        let key = (src.to_owned(), msg_id);
+        if let Some(seen) = self.seen.get(&key) {
+            return Some(seen.clone());
+        }
+        // A capacity of zero would mean that nothing is remembered
+        while self.seen.len() >= self.config.capacity.max(1) {
+            let Some((_, oldest)) = self.order.pop_front() else {
+                break;
+            };
+            self.seen.remove(&oldest);
+        }
+        self.order
+            .push_back((Instant::now() + self.config.ttl, key.clone()));
+        self.seen.insert(key, Seen::Pending(Vec::new()));
+        None
+    }
+
+    /// Records the replies that a handler returned for a message, along with any replies that were
+    /// already sent through the node's `Sender`. If the message has been forgotten in the
+    /// meantime, it stays forgotten.
+    pub(crate) fn record(
+        &mut self,
+        src: &str,
+        msg_id: MessageId,
+        replies: Vec<Message<OrError<B>>>,
+    ) {
+        if let Some(seen) = self.seen.get_mut(&(src.to_owned(), msg_id)) {
+            let (Seen::Pending(sent) | Seen::Replied(sent)) = seen;
+            let mut sent = std::mem::take(sent);
+            sent.extend(replies);
+            *seen = Seen::Replied(sent);
+        }
+    }
+
+    /// Records a reply that was sent through the node's `Sender`, if it answers a message that is
+    /// remembered. The reply can be sent while the handler is still running or after it has
+    /// finished (e.g. from a spawned task).
+    pub(crate) fn record_sent(&mut self, reply: &Message<B>) {
+        let Some(msg_id) = reply.body.in_reply_to() else {
+            return;
+        };
+        if let Some(Seen::Pending(sent) | Seen::Replied(sent)) =
+            self.seen.get_mut(&(reply.dest.clone(), msg_id))
+        {
+            sent.push(reply.clone().map_body(OrError::Main));
+        }
+    }
+
+    /// Forgets the messages that have been remembered for longer than the configured time.
+    fn expire(&mut self) {
+        let now = Instant::now();
+        while let Some((at, _)) = self.order.front() {
+            if *at > now {
+                break;
+            }
+            if let Some((_, key)) = self.order.pop_front() {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{MessageBody, MessageId};
+
+/// The errors that the client can encounter while running a node.
+#[derive(Debug)]
+pub enum Error {
+    /// An error occurred while reading from the input or writing to the output
+    Io(std::io::Error),
+    /// The input was closed
+    InputClosed,
+    /// An inbound line could not be deserialized into a message
+    Malformed {
+        /// The line that was read
+        line: String,
+        /// The reason the line could not be deserialized
+        source: serde_json::Error,
+    },
+    /// An outbound message could not be serialized
+    Serialize(serde_json::Error),
+    /// The first message that was received was not an `Init` message
+    MissingInit(String),
+    /// An `Init` message was received after the node had been initialized
+    DuplicateInit(String),
+    /// The node sent messages through its sender while it was being constructed
+    SendDuringInit,
+}
+
+/// The standard error codes that Maelstrom defines. Codes that Maelstrom does not define are
+/// captured by the `Custom` variant.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(from = "u64", into = "u64")]
+pub enum ErrorCode {
+    /// The requested operation could not be completed within a timeout
+    Timeout,
+    /// The requested operation was sent to a node that does not exist
+    NodeNotFound,
+    /// The requested operation is not supported by the node
+    NotSupported,
+    /// The requested operation could not be handled right now, but it might be possible later
+    TemporarilyUnavailable,
+    /// The request was malformed, e.g. it was missing fields or had fields of the wrong type
+    MalformedRequest,
+    /// The node encountered an unexpected error while handling the request
+    Crash,
+    /// The requested operation was aborted and definitely did not take place
+    Abort,
+    /// The requested operation required a key that does not exist
+    KeyDoesNotExist,
+    /// The requested operation tried to create a key that already exists
+    KeyAlreadyExists,
+    /// The requested operation required some condition to hold but it did not
+    PreconditionFailed,
+    /// The requested transaction was aborted because of a conflict with another transaction
+    TxnConflict,
+    /// An error code that is not defined by Maelstrom. Maelstrom reserves codes 1000 and above for
+    /// these.
+    Custom(u64),
+}
+
+/// The message body type used to communicate that a request could not be handled.
+///
+/// To mix this body into a node's body type, use `OrError`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "TaggedErrorBody", into = "TaggedErrorBody")]
+pub struct ErrorBody {
+    /// The message id, if any
+    pub msg_id: Option<MessageId>,
+    /// The id of the message that caused the error, if it had one
+    pub in_reply_to: Option<MessageId>,
+    /// The kind of error that occurred
+    pub code: ErrorCode,
+    /// A human-readable description of the error
+    pub text: Option<String>,
+}
+
+/// Mixes the `ErrorBody` into another body type. When this is deserialized on its own, bodies are
+/// first deserialized as the main body type and then as an error. The client does not do this;
+/// it picks the type from the body's `type` field (see `Inbound`).
+///
+/// Bodies whose type is neither are kept as raw JSON in `Unknown`. These are only created by
+/// `Inbound`, so that a body with a known type but a bad field is still treated as malformed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged, bound = "B: MessageBody")]
+pub enum OrError<B: MessageBody> {
+    /// The main body type
+    Main(B),
+    /// An error body
+    Error(ErrorBody),
+    /// A body whose type is not known
+    #[serde(skip_deserializing)]
+    Unknown(Value),
+}
+
+/// Serde can't check the tag of an internally tagged struct, so the error body is (de)serialized
+/// through this type.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+enum TaggedErrorBody {
+    #[serde(rename = "error")]
+    Error {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        msg_id: Option<MessageId>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        in_reply_to: Option<MessageId>,
+        code: ErrorCode,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+    },
+}
+
+impl ErrorCode {
+    /// Returns the numeric value of the code as it is sent over the wire.
+    pub fn code(&self) -> u64 {
+        match self {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Custom(code) => *code,
+        }
+    }
+
+    /// Returns if the error is definite, i.e. the requested operation definitely did not take
+    /// place. Indefinite errors (timeouts, crashes, and custom errors) mean that the operation
+    /// might have happened.
+    pub fn is_definite(&self) -> bool {
+        !matches!(
+            self,
+            ErrorCode::Timeout | ErrorCode::Crash | ErrorCode::Custom(_)
+        )
+    }
+}
+
+impl ErrorBody {
+    /// Creates a new error body with the given code and description.
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            msg_id: None,
+            in_reply_to: None,
+            code,
+            text: Some(text.into()),
+        }
+    }
+
+    /// Creates a `not-supported` error for a message whose body has a type that the node does not
+    /// know.
+    pub fn unknown_type(body: &Value) -> Self {
+        match body.get("type").and_then(Value::as_str) {
+            Some(kind) => Self::new(
+                ErrorCode::NotSupported,
+                format!("unknown body type `{kind}`"),
+            ),
+            None => Self::new(ErrorCode::NotSupported, "unknown body type"),
+        }
+    }
+
+    /// Returns if the error is definite. See `ErrorCode::is_definite`.
+    pub fn is_definite(&self) -> bool {
+        self.code.is_definite()
+    }
+
+    /// Creates the error body that should be sent back when a handler fails. If the error is an
+    /// `ErrorBody` or `ErrorCode`, it is used as is. Otherwise, a `Crash` error is created using
+    /// the error's description.
+    pub fn from_handler_err(err: &anyhow::Error) -> Self {
+        if let Some(body) = err.downcast_ref::<ErrorBody>() {
+            body.clone()
+        } else if let Some(code) = err.downcast_ref::<ErrorCode>() {
+            ErrorBody::from(*code)
+        } else {
+            ErrorBody::new(ErrorCode::Crash, format!("{err:#}"))
+        }
+    }
+}
+
+impl MessageBody for ErrorBody {
+    fn update_msg_id(&mut self, id: MessageId) {
+        self.msg_id = Some(id);
+    }
+
+    fn msg_id(&self) -> Option<MessageId> {
+        self.msg_id
+    }
+
+    fn in_reply_to(&self) -> Option<MessageId> {
+        self.in_reply_to
+    }
+
+    fn update_in_reply_to(&mut self, id: MessageId) {
+        self.in_reply_to = Some(id);
+    }
+
+    fn is_reply(&self) -> bool {
+        true
+    }
+
+    fn knows_type(kind: &str) -> Option<bool> {
+        Some(kind == "error")
+    }
+}
+
+impl<B: MessageBody> MessageBody for OrError<B> {
+    fn update_msg_id(&mut self, id: MessageId) {
+        match self {
+            OrError::Main(body) => body.update_msg_id(id),
+            OrError::Error(body) => body.update_msg_id(id),
+            OrError::Unknown(body) => body.update_msg_id(id),
+        }
+    }
+
+    fn msg_id(&self) -> Option<MessageId> {
+        match self {
+            OrError::Main(body) => body.msg_id(),
+            OrError::Error(body) => body.msg_id(),
+            OrError::Unknown(body) => body.msg_id(),
+        }
+    }
+
+    fn in_reply_to(&self) -> Option<MessageId> {
+        match self {
+            OrError::Main(body) => body.in_reply_to(),
+            OrError::Error(body) => body.in_reply_to(),
+            OrError::Unknown(body) => body.in_reply_to(),
+        }
+    }
+
+    fn is_reply(&self) -> bool {
+        match self {
+            OrError::Main(body) => body.is_reply(),
+            OrError::Error(_) => true,
+            OrError::Unknown(body) => body.is_reply(),
+        }
+    }
+
+    fn update_in_reply_to(&mut self, id: MessageId) {
+        match self {
+            OrError::Main(body) => body.update_in_reply_to(id),
+            OrError::Error(body) => body.update_in_reply_to(id),
+            OrError::Unknown(body) => body.update_in_reply_to(id),
+        }
+    }
+
+    fn knows_type(kind: &str) -> Option<bool> {
+        match kind {
+            "error" => Some(true),
+            kind => B::knows_type(kind),
+        }
+    }
+}
+
+impl Error {
+    /// Returns if the error was caused by a bad inbound line, in which case the client can keep
+    /// running.
+    pub fn is_malformed(&self) -> bool {
+        matches!(self, Error::Malformed { .. } | Error::DuplicateInit(_))
+    }
+
+    /// Returns the line that caused the error, if any.
+    pub fn line(&self) -> Option<&str> {
+        match self {
+            Error::Malformed { line, .. }
+            | Error::MissingInit(line)
+            | Error::DuplicateInit(line) => Some(line),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+impl From<u64> for ErrorCode {
+    fn from(value: u64) -> Self {
+        match value {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Custom(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u64 {
+    fn from(value: ErrorCode) -> Self {
+        value.code()
+    }
+}
+
+impl From<ErrorCode> for ErrorBody {
+    fn from(code: ErrorCode) -> Self {
+        Self {
+            msg_id: None,
+            in_reply_to: None,
+            code,
+            text: None,
+        }
+    }
+}
+
+impl From<TaggedErrorBody> for ErrorBody {
+    fn from(value: TaggedErrorBody) -> Self {
+        let TaggedErrorBody::Error {
+            msg_id,
+            in_reply_to,
+            code,
+            text,
+        } = value;
+        Self {
+            msg_id,
+            in_reply_to,
+            code,
+            text,
+        }
+    }
+}
+
+impl From<ErrorBody> for TaggedErrorBody {
+    fn from(value: ErrorBody) -> Self {
+        let ErrorBody {
+            msg_id,
+            in_reply_to,
+            code,
+            text,
+        } = value;
+        TaggedErrorBody::Error {
+            msg_id,
+            in_reply_to,
+            code,
+            text,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+            Error::InputClosed => write!(f, "the input was closed"),
+            Error::Malformed { line, source } => {
+                write!(f, "failed to parse inbound message ({source}): {line}")
+            }
+            Error::Serialize(err) => write!(f, "failed to serialize outbound message: {err}"),
+            Error::MissingInit(line) => write!(f, "first message was not an init message: {line}"),
+            Error::DuplicateInit(line) => write!(f, "received a second init message: {line}"),
+            Error::SendDuringInit => {
+                write!(f, "nodes should not send messages during construction")
+            }
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorCode::Timeout => write!(f, "timeout"),
+            ErrorCode::NodeNotFound => write!(f, "node-not-found"),
+            ErrorCode::NotSupported => write!(f, "not-supported"),
+            ErrorCode::TemporarilyUnavailable => write!(f, "temporarily-unavailable"),
+            ErrorCode::MalformedRequest => write!(f, "malformed-request"),
+            ErrorCode::Crash => write!(f, "crash"),
+            ErrorCode::Abort => write!(f, "abort"),
+            ErrorCode::KeyDoesNotExist => write!(f, "key-does-not-exist"),
+            ErrorCode::KeyAlreadyExists => write!(f, "key-already-exists"),
+            ErrorCode::PreconditionFailed => write!(f, "precondition-failed"),
+            ErrorCode::TxnConflict => write!(f, "txn-conflict"),
+            ErrorCode::Custom(code) => write!(f, "custom error {code}"),
+        }
+    }
+}
+
+impl Display for ErrorBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.text {
+            Some(text) => write!(f, "{} ({}): {text}", self.code, self.code.code()),
+            None => write!(f, "{} ({})", self.code, self.code.code()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Malformed { source, .. } | Error::Serialize(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for ErrorCode {}
+
+impl std::error::Error for ErrorBody {}
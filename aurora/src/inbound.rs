@@ -0,0 +1,109 @@
+use std::borrow::Cow;
+
+use serde::{de::Error as _, Deserialize};
+use serde_json::{value::RawValue, Map, Value};
+
+use crate::{InitBody, Message, MessageBody, MessageId, OrError, ParseReply};
+
+/// A line read from the input, decoded according to the `type` of its body.
+///
+/// The body's type is read first, and then the body is deserialized directly into the matching
+/// type. This means that every line is only parsed once and that the errors for bad bodies name
+/// the field that could not be parsed.
+#[derive(Debug, PartialEq)]
+pub enum Inbound<B: MessageBody> {
+    /// A message for the node. This is an error if the body's type is `error` and is unknown if the
+    /// node's body type does not know the body's type.
+    Main(Message<OrError<B>>),
+    /// An `Init` or `InitOk` message
+    Init(Message<InitBody>),
+}
+
+/// A message whose body has not been parsed yet.
+#[derive(Deserialize, Debug)]
+struct RawMessage<'a> {
+    src: String,
+    dest: String,
+    #[serde(borrow)]
+    body: &'a RawValue,
+    #[serde(default)]
+    id: Option<u64>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// The part of a body that says what type it is and what it is responding to.
+#[derive(Deserialize, Debug)]
+pub(crate) struct Tag<'a> {
+    #[serde(rename = "type", borrow)]
+    pub(crate) kind: Cow<'a, str>,
+    /// This is kept raw, since bodies that the node does not know can use the field for anything
+    #[serde(default, borrow)]
+    in_reply_to: Option<&'a RawValue>,
+}
+
+impl<B: MessageBody> Inbound<B> {
+    /// Decodes a line from the input.
+    ///
+    /// Bodies whose type the node's body type does not know are kept as raw JSON (see
+    /// `OrError::Unknown`). Bodies with a known type that fail to parse are errors.
+    pub fn from_line(line: &str) -> serde_json::Result<Self> {
+        Self::from_line_with(line, |_| None)
+    }
+
+    /// The same as `Inbound::from_line`, but replies to the given ids are read with the given
+    /// parser first. The client uses this to read replies to `Sender::rpc_as`.
+    pub(crate) fn from_line_with(
+        line: &str,
+        parse_reply: impl FnOnce(MessageId) -> Option<ParseReply<B>>,
+    ) -> serde_json::Result<Self> {
+        let RawMessage {
+            src,
+            dest,
+            body,
+            id,
+            extra,
+        } = serde_json::from_str(line)?;
+        let Tag { kind, in_reply_to } = serde_json::from_str(body.get())
+            .map_err(|err| serde_json::Error::custom(format!("invalid body: {err}")))?;
+        let reply = in_reply_to
+            .and_then(|id| serde_json::from_str(id.get()).ok())
+            .and_then(parse_reply)
+            .and_then(|parse| parse(body.get()).ok());
+        let invalid = |err| serde_json::Error::custom(format!("invalid `{kind}` body: {err}"));
+        let body = match kind.as_ref() {
+            "init" | "init_ok" => {
+                let body = serde_json::from_str(body.get()).map_err(invalid)?;
+                return Ok(Inbound::Init(Message {
+                    src,
+                    dest,
+                    body,
+                    id,
+                    extra,
+                }));
+            }
+            "error" => OrError::Error(serde_json::from_str(body.get()).map_err(invalid)?),
+            kind => match (reply, B::knows_type(kind)) {
+                (Some(reply), _) => OrError::Main(reply),
+                (None, Some(false)) => {
+                    OrError::Unknown(serde_json::from_str(body.get()).map_err(invalid)?)
+                }
+                (None, known) => match serde_json::from_str(body.get()) {
+                    Ok(body) => OrError::Main(body),
+                    // Without a list of types, there is no telling a bad body from an unknown one
+                    Err(_) if known.is_none() => {
+                        OrError::Unknown(serde_json::from_str(body.get()).map_err(invalid)?)
+                    }
+                    Err(err) => return Err(invalid(err)),
+                },
+            },
+        };
+        Ok(Inbound::Main(Message {
+            src,
+            dest,
+            body,
+            id,
+            extra,
+        }))
+    }
+}
@@ -0,0 +1,404 @@
+use std::{fmt::Display, marker::PhantomData, time::Duration};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    BodyTypes, ErrorBody, ErrorCode, Held, Holds, MessageBody, MessageId, NodeContext, RetryPolicy,
+    RpcError,
+};
+
+/// The name of Maelstrom's linearizable key-value service.
+pub const LIN_KV: &str = "lin-kv";
+
+/// The name of Maelstrom's sequentially consistent key-value service.
+pub const SEQ_KV: &str = "seq-kv";
+
+/// The name of Maelstrom's last-write-wins key-value service.
+pub const LWW_KV: &str = "lww-kv";
+
+/// One of Maelstrom's key-value services. The service that a `KvClient` talks to is part of its
+/// type, so the helpers that are only sound under some consistency models are only available for
+/// the services that provide them.
+pub trait KvService: std::fmt::Debug + Clone + Copy + Send + Sync + 'static {
+    /// The name of the service's node
+    const NAME: &'static str;
+}
+
+/// Key-value services that apply every operation in a single order that all nodes agree on, which
+/// makes compare-and-set atomic.
+pub trait Ordered: KvService {}
+
+/// The `lin-kv` service. Every operation takes effect at some point between its request and its
+/// reply, so reads always see the latest write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinKv;
+
+/// The `seq-kv` service. Operations are applied in an order that respects the order of each
+/// node's own operations, but reads can return stale values: a node is only guaranteed to see
+/// the writes that happened before its own last operation. Use `KvClient::read_fresh` for reads
+/// that need to see recent writes from other nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeqKv;
+
+/// The `lww-kv` service. Writes are merged by timestamp, and reads can be arbitrarily stale. As
+/// there is no single order of operations, compare-and-set is not offered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LwwKv;
+
+/// A client for `lin-kv`.
+pub type LinKvClient<B> = KvClient<B, LinKv>;
+
+/// A client for `seq-kv`.
+pub type SeqKvClient<B> = KvClient<B, SeqKv>;
+
+/// A client for `lww-kv`.
+pub type LwwKvClient<B> = KvClient<B, LwwKv>;
+
+/// The message body type used to talk to Maelstrom's key-value services. Keys and values can be any
+/// JSON value.
+#[derive(MessageBody, Debug, Clone, PartialEq, Eq)]
+pub enum KvBody {
+    /// Reads the value of a key
+    Read {
+        /// The message id
+        msg_id: MessageId,
+        /// The key to read
+        key: Value,
+    },
+    /// The value of the key that was read
+    ReadOk {
+        /// The message id, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        msg_id: Option<MessageId>,
+        /// The id of the message that this is responding to
+        in_reply_to: MessageId,
+        /// The key's value
+        value: Value,
+    },
+    /// Sets the value of a key
+    Write {
+        /// The message id
+        msg_id: MessageId,
+        /// The key to write
+        key: Value,
+        /// The key's new value
+        value: Value,
+    },
+    /// The key was written
+    WriteOk {
+        /// The message id, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        msg_id: Option<MessageId>,
+        /// The id of the message that this is responding to
+        in_reply_to: MessageId,
+    },
+    /// Sets the value of a key, but only if its current value is `from`
+    Cas {
+        /// The message id
+        msg_id: MessageId,
+        /// The key to write
+        key: Value,
+        /// The value that the key needs to have
+        from: Value,
+        /// The key's new value
+        to: Value,
+        /// Whether the key is created with the value `to` if it does not exist
+        #[serde(default, skip_serializing_if = "is_false")]
+        create_if_not_exists: bool,
+    },
+    /// The key was compared and set
+    CasOk {
+        /// The message id, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        msg_id: Option<MessageId>,
+        /// The id of the message that this is responding to
+        in_reply_to: MessageId,
+    },
+}
+
+/// A client for one of Maelstrom's key-value services. Requests are sent as RPCs through the
+/// node's sender, so the client can be used from any handler or background task. Clients are
+/// cheap to clone.
+///
+/// The client works with any body type that holds a `KvBody` (see `Holds`), so nodes can talk to
+/// the service with their own body type (which can also hold other services' bodies). The service is picked by the second type parameter,
+/// which is easiest to do with the `LinKvClient`, `SeqKvClient`, and `LwwKvClient` aliases.
+#[derive(Debug, Clone)]
+pub struct KvClient<B: MessageBody, S: KvService> {
+    ctx: NodeContext<B>,
+    held: Held<B, KvBody>,
+    /// How `KvClient::update` backs off between conflicts and when it gives up
+    retry: RetryPolicy,
+    service: PhantomData<S>,
+}
+
+/// The errors that can occur while using a `KvClient`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvError {
+    /// The key does not exist (error code 20)
+    KeyDoesNotExist,
+    /// The key's value was not the one that a compare-and-set expected (error code 22)
+    PreconditionFailed,
+    /// The service replied with some other error
+    Remote(ErrorBody),
+    /// The request could not be sent or its reply could not be received
+    Rpc(RpcError),
+    /// A key or value could not be converted to or from JSON, or the service replied with the
+    /// wrong type of body
+    Invalid(String),
+    /// An update kept conflicting with other nodes' writes until its deadline passed
+    Contended {
+        /// The number of compare-and-sets that were tried
+        attempts: u32,
+    },
+}
+
+impl KvService for LinKv {
+    const NAME: &'static str = LIN_KV;
+}
+
+impl KvService for SeqKv {
+    const NAME: &'static str = SEQ_KV;
+}
+
+impl KvService for LwwKv {
+    const NAME: &'static str = LWW_KV;
+}
+
+impl Ordered for LinKv {}
+
+impl Ordered for SeqKv {}
+
+impl<B: MessageBody, S: KvService> KvClient<B, S> {
+    /// Creates a client for the service. Updates retry conflicts with a short backoff and give up
+    /// after five seconds.
+    pub fn new<I>(ctx: &NodeContext<B>) -> Self
+    where
+        B: Holds<KvBody, I>,
+    {
+        let retry = RetryPolicy {
+            initial: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(500),
+            multiplier: 2,
+            jitter: Duration::from_millis(10),
+            deadline: Some(Duration::from_secs(5)),
+        };
+        Self::with_retry(ctx, retry)
+    }
+
+    /// Creates a client whose updates retry conflicts according to the given policy. Without a
+    /// deadline, updates retry until they succeed.
+    pub fn with_retry<I>(ctx: &NodeContext<B>, retry: RetryPolicy) -> Self
+    where
+        B: Holds<KvBody, I>,
+    {
+        Self {
+            ctx: ctx.clone(),
+            held: Held::new(),
+            retry,
+            service: PhantomData,
+        }
+    }
+
+    /// Returns the name of the service that the client talks to.
+    pub fn service(&self) -> &'static str {
+        S::NAME
+    }
+
+    /// Reads the value of a key.
+    pub async fn read<K, V>(&self, key: K) -> Result<V, KvError>
+    where
+        K: Serialize,
+        V: DeserializeOwned,
+    {
+        let body = KvBody::Read {
+            msg_id: MessageId::default(),
+            key: to_json(key)?,
+        };
+        match self.call(body).await? {
+            KvBody::ReadOk { value, .. } => {
+                serde_json::from_value(value).map_err(|err| KvError::Invalid(err.to_string()))
+            }
+            body => Err(unexpected("read_ok", &body)),
+        }
+    }
+
+    /// Sets the value of a key.
+    pub async fn write<K, V>(&self, key: K, value: V) -> Result<(), KvError>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let body = KvBody::Write {
+            msg_id: MessageId::default(),
+            key: to_json(key)?,
+            value: to_json(value)?,
+        };
+        match self.call(body).await? {
+            KvBody::WriteOk { .. } => Ok(()),
+            body => Err(unexpected("write_ok", &body)),
+        }
+    }
+
+    /// Sends a request to the service and waits for its reply.
+    async fn call(&self, body: KvBody) -> Result<KvBody, KvError> {
+        let msg = self.ctx.message(S::NAME, (self.held.wrap)(body));
+        let reply = self.ctx.sender().rpc_with(msg, self.held.parse).await?;
+        (self.held.unwrap)(reply.body)
+            .ok_or_else(|| KvError::Invalid(String::from("the reply was not a key-value body")))
+    }
+}
+
+impl<B: MessageBody, S: Ordered> KvClient<B, S> {
+    /// Sets the value of a key to `to`, but only if its current value is `from`. If the key does
+    /// not exist and `create_if_not_exists` is set, the key is created with the value `to`.
+    pub async fn cas<K, V>(
+        &self,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+    ) -> Result<(), KvError>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let body = KvBody::Cas {
+            msg_id: MessageId::default(),
+            key: to_json(key)?,
+            from: to_json(from)?,
+            to: to_json(to)?,
+            create_if_not_exists,
+        };
+        match self.call(body).await? {
+            KvBody::CasOk { .. } => Ok(()),
+            body => Err(unexpected("cas_ok", &body)),
+        }
+    }
+
+    /// Applies `f` to the value of a key with a compare-and-set, retrying with the key's new value
+    /// whenever another node changed it first. A key that does not exist is treated as having the
+    /// value `default`. Returns the value that was written.
+    ///
+    /// Retries back off according to the client's `RetryPolicy`, so that nodes contending for the
+    /// same key spread out. If the policy's deadline would pass before the next retry,
+    /// `KvError::Contended` is returned.
+    ///
+    /// `f` can be called any number of times, so it should not have side effects.
+    pub async fn update<K, V, F>(&self, key: K, default: V, mut f: F) -> Result<V, KvError>
+    where
+        K: Serialize,
+        V: Serialize + DeserializeOwned + Clone,
+        F: FnMut(&V) -> V,
+    {
+        let key = to_json(key)?;
+        let deadline = self
+            .retry
+            .deadline
+            .map(|deadline| self.ctx.now() + deadline);
+        let mut backoff = self.retry.initial;
+        let mut attempts = 0;
+        let (mut current, mut exists) = match self.read(&key).await {
+            Ok(val) => (val, true),
+            Err(KvError::KeyDoesNotExist) => (default.clone(), false),
+            Err(err) => return Err(err),
+        };
+        loop {
+            let next = f(&current);
+            attempts += 1;
+            match self.cas(&key, current, next.clone(), !exists).await {
+                Ok(()) => return Ok(next),
+                Err(KvError::PreconditionFailed) => {}
+                Err(err) => return Err(err),
+            }
+            let (wait, next_backoff) = self.retry.wait(&self.ctx, backoff);
+            backoff = next_backoff;
+            if deadline.is_some_and(|deadline| self.ctx.now() + wait > deadline) {
+                return Err(KvError::Contended { attempts });
+            }
+            tokio::time::sleep(wait).await;
+            (current, exists) = match self.read(&key).await {
+                Ok(val) => (val, true),
+                Err(KvError::KeyDoesNotExist) => (default.clone(), false),
+                Err(err) => return Err(err),
+            };
+        }
+    }
+}
+
+impl<B: MessageBody> KvClient<B, SeqKv> {
+    /// Makes sure that later reads see every write that finished before this call, including
+    /// those of other nodes.
+    ///
+    /// `seq-kv` only promises that a node's reads see the writes that happened before its own
+    /// last operation, so this writes a value that has not been written before to a key that
+    /// belongs to this node. Once that write is acknowledged, the node's view of the store is at
+    /// least as new as the write.
+    pub async fn barrier(&self) -> Result<(), KvError> {
+        let key = format!("aurora/barrier/{}", self.ctx.node_id());
+        let value = format!("{}/{}", self.ctx.node_id(), self.ctx.next_id().0);
+        self.write(key, value).await
+    }
+
+    /// Reads the value of a key after a `KvClient::barrier`, so the value is at least as new as
+    /// every write that finished before this call.
+    pub async fn read_fresh<K, V>(&self, key: K) -> Result<V, KvError>
+    where
+        K: Serialize,
+        V: DeserializeOwned,
+    {
+        self.barrier().await?;
+        self.read(key).await
+    }
+}
+
+impl From<RpcError> for KvError {
+    fn from(err: RpcError) -> Self {
+        match err {
+            RpcError::Remote(err) => match err.code {
+                ErrorCode::KeyDoesNotExist => KvError::KeyDoesNotExist,
+                ErrorCode::PreconditionFailed => KvError::PreconditionFailed,
+                _ => KvError::Remote(err),
+            },
+            err => KvError::Rpc(err),
+        }
+    }
+}
+
+impl Display for KvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KvError::KeyDoesNotExist => write!(f, "the key does not exist"),
+            KvError::PreconditionFailed => write!(f, "the key did not have the expected value"),
+            KvError::Remote(err) => write!(f, "the service replied with an error: {err}"),
+            KvError::Rpc(err) => write!(f, "the request failed: {err}"),
+            KvError::Invalid(err) => write!(f, "invalid key-value data: {err}"),
+            KvError::Contended { attempts } => {
+                write!(
+                    f,
+                    "the key kept changing, so the update gave up after {attempts} tries"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+/// Converts a key or value to JSON.
+fn to_json<T: Serialize>(val: T) -> Result<Value, KvError> {
+    serde_json::to_value(val).map_err(|err| KvError::Invalid(err.to_string()))
+}
+
+/// Creates the error for a reply with the wrong type.
+fn unexpected(expected: &str, body: &KvBody) -> KvError {
+    KvError::Invalid(format!(
+        "expected a `{expected}` reply but got `{}`",
+        body.body_type()
+    ))
+}
+
+fn is_false(val: &bool) -> bool {
+    !*val
+}
@@ -26,31 +26,183 @@ use std::{
     fmt::Debug,
 };
 
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+// Lets the code that `#[derive(MessageBody)]` generates name this crate from inside of it
+extern crate self as aurora;
 
 mod client;
+mod cluster;
+mod compose;
+mod config;
+mod context;
+mod dedup;
+mod error;
+mod inbound;
+mod kv;
+mod logging;
 mod message;
 mod node;
+mod queue;
+mod reliable;
+mod rpc;
+mod services;
+mod shutdown;
+mod timer;
+mod tso;
+mod writer;
 
 pub use client::*;
+pub use cluster::*;
+pub use compose::*;
+pub use config::*;
+pub use context::*;
+pub(crate) use dedup::*;
+pub use error::*;
+pub use inbound::*;
+pub use kv::*;
+pub use logging::*;
 pub use message::*;
 pub use node::*;
+pub use queue::*;
+pub use reliable::*;
+pub use rpc::*;
+pub use services::*;
+pub(crate) use shutdown::*;
+pub use timer::*;
+pub use tso::*;
+pub use writer::*;
+
+/// Re-exported so that nodes can use `NodeRng` without depending on a matching version of `rand`.
+pub use rand;
+
+/// Re-exported so that nodes can match on `OneOf` bodies without depending on a matching version
+/// of `either`.
+pub use either;
+
+/// Derives `MessageBody` and `BodyTypes` for a body enum, along with the serde implementations that
+/// read and write it the way that Maelstrom expects. See the `aurora_derive` crate for details.
+pub use aurora_derive::MessageBody;
+
+/// Not public API. This is used by the code that `#[derive(MessageBody)]` generates.
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+}
 
 /// A super trait to create a shorthand for all the traits that a message body needs as they are
 /// used as bounds in lots of places.
-pub trait MessageBody: Serialize + DeserializeOwned + Debug + Clone + PartialEq + Eq {
-    /// This method updates the message id of the body, if applicable
-    fn update_msg_id(&mut self, id: MessageId);
+pub trait MessageBody:
+    Serialize + DeserializeOwned + Debug + Clone + PartialEq + Eq + Send + 'static
+{
+    /// This method updates the message id of the body, if applicable. The client uses this to
+    /// stamp a fresh message id on outbound messages.
+    fn update_msg_id(&mut self, _id: MessageId) {}
+
+    /// This method updates the id of the message that the body is responding to, if applicable.
+    /// `Message::into_response` uses this to fill in replies. Bodies that are not responses should
+    /// ignore this.
+    fn update_in_reply_to(&mut self, _id: MessageId) {}
+
+    /// Returns the message id of the body, if it has one. Bodies without message ids can not be
+    /// sent via `Sender::rpc`.
+    fn msg_id(&self) -> Option<MessageId> {
+        None
+    }
+
+    /// Returns the id of the message that this body is responding to, if it is a response. The
+    /// client uses this to route replies to outstanding RPCs.
+    fn in_reply_to(&self) -> Option<MessageId> {
+        None
+    }
+
+    /// Returns whether the body is a reply to some request. Bodies that derive `MessageBody` answer
+    /// this with `BodyTypes::is_response`. By default, bodies that are responding to a message
+    /// are replies.
+    fn is_reply(&self) -> bool {
+        self.in_reply_to().is_some()
+    }
+
+    /// Returns whether the body type has a body whose `type` field is `kind`. Bodies that derive
+    /// `MessageBody` answer this from `BodyTypes::TYPES`. Body types that do not know return
+    /// `None`, in which case every body that they fail to parse is treated as having an unknown
+    /// type.
+    fn knows_type(_kind: &str) -> Option<bool> {
+        None
+    }
+}
+
+/// Describes the types that a body can have (as written in its `type` field) and which of them are
+/// requests and responses. This is implemented by `#[derive(MessageBody)]`.
+pub trait BodyTypes: MessageBody {
+    /// Every type that the body can have
+    const TYPES: &'static [&'static str];
+
+    /// The types of requests that have responses, paired with the types of those responses
+    const PAIRS: &'static [(&'static str, &'static str)];
+
+    /// Returns the type of this body.
+    fn body_type(&self) -> &'static str;
+
+    /// Returns the type of the response to this body, if this body is a request that has one.
+    fn response_type(&self) -> Option<&'static str> {
+        let kind = self.body_type();
+        Self::PAIRS
+            .iter()
+            .find(|(req, _)| *req == kind)
+            .map(|(_, resp)| *resp)
+    }
+
+    /// Returns whether this body is the response to some type of request.
+    fn is_response(&self) -> bool {
+        let kind = self.body_type();
+        Self::PAIRS.iter().any(|(_, resp)| *resp == kind)
+    }
+}
+
+/* ------ Raw ------ */
+
+/// Raw JSON bodies. The message id and the id that the body is responding to are read from the
+/// `msg_id` and `in_reply_to` fields. Since there is no telling if a raw body is a response,
+/// `in_reply_to` is only filled in if the field is already there.
+impl MessageBody for Value {
+    fn update_msg_id(&mut self, id: MessageId) {
+        if let Some(body) = self.as_object_mut() {
+            body.insert(String::from("msg_id"), id.0.into());
+        }
+    }
+
+    fn update_in_reply_to(&mut self, id: MessageId) {
+        if let Some(in_reply_to) = self.get_mut("in_reply_to") {
+            *in_reply_to = id.0.into();
+        }
+    }
+
+    fn knows_type(_: &str) -> Option<bool> {
+        Some(true)
+    }
+
+    fn msg_id(&self) -> Option<MessageId> {
+        raw_id(self, "msg_id")
+    }
+
+    fn in_reply_to(&self) -> Option<MessageId> {
+        raw_id(self, "in_reply_to")
+    }
+}
+
+/// Reads a message id out of a field of a raw body.
+fn raw_id(body: &Value, field: &str) -> Option<MessageId> {
+    body.get(field)?.as_u64().map(|id| MessageId(id as usize))
 }
 
 /* ------ Init ------ */
 
 /// The message body type used to establish a node
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-#[serde(tag = "type")]
+#[derive(MessageBody, Debug, Clone, PartialEq, Eq)]
 pub enum InitBody {
     /// This data is passed to the `init` method of the `Node` trait to construct the `Node`.
-    #[serde(rename = "init")]
     Init {
         /// The message id
         msg_id: Option<MessageId>,
@@ -60,7 +212,6 @@ pub enum InitBody {
         node_ids: Vec<String>,
     },
     /// This data communicates the the `Node` was succeesfully established
-    #[serde(rename = "init_ok")]
     InitOk {
         /// The message id
         msg_id: MessageId,
@@ -69,23 +220,12 @@ pub enum InitBody {
     },
 }
 
-impl MessageBody for InitBody {
-    fn update_msg_id(&mut self, id: MessageId) {
-        match self {
-            InitBody::Init { msg_id, .. } => *msg_id = Some(id),
-            InitBody::InitOk { msg_id, .. } => *msg_id = id,
-        }
-    }
-}
-
 /* ------ Echo ------ */
 
 /// The message body type used in the echo problem
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-#[serde(tag = "type")]
+#[derive(MessageBody, Debug, Clone, PartialEq, Eq)]
 pub enum EchoBody {
     /// The data that communicates an echo action
-    #[serde(rename = "echo")]
     Echo {
         /// The message id
         msg_id: MessageId,
@@ -93,7 +233,6 @@ pub enum EchoBody {
         echo: String,
     },
     /// The data returned by the node as part of the echo response
-    #[serde(rename = "echo_ok")]
     EchoOk {
         /// The message that is being echoed back
         echo: String,
@@ -104,43 +243,33 @@ pub enum EchoBody {
     },
 }
 
-impl MessageBody for EchoBody {
-    fn update_msg_id(&mut self, id: MessageId) {
-        match self {
-            EchoBody::Echo { msg_id, .. } | EchoBody::EchoOk { msg_id, .. } => *msg_id = id,
-        }
-    }
-}
-
 /* ------ Ids ------ */
 
 /// The message body type used in the unique id generation problem
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-#[serde(tag = "type")]
+#[derive(MessageBody, Debug, Clone, PartialEq, Eq)]
 pub enum IdBody {
     /// The data that communicates a unique id generation action
-    #[serde(rename = "generate")]
-    Generate,
+    Generate {
+        /// The message id
+        msg_id: MessageId,
+    },
     /// The data returned by the node as part of the unique id generation response
-    #[serde(rename = "generate_ok")]
     GenerateOk {
+        /// The message id
+        msg_id: MessageId,
+        /// The id of the message that this is responding to
+        in_reply_to: MessageId,
         /// The id that was generated
         id: String,
     },
 }
 
-impl MessageBody for IdBody {
-    fn update_msg_id(&mut self, _: MessageId) {}
-}
-
 /* ------ Broadcast ------ */
 
 /// The message body type used in the broadcast problem
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-#[serde(tag = "type")]
+#[derive(MessageBody, Debug, Clone, PartialEq, Eq)]
 pub enum BroadcastBody {
     /// The data that communicates that a messages needs to be gossipped across the cluster
-    #[serde(rename = "broadcast")]
     Broadcast {
         /// The message id
         msg_id: MessageId,
@@ -148,7 +277,6 @@ pub enum BroadcastBody {
         message: usize,
     },
     /// The data that communicates that the broadcast request is being processed
-    #[serde(rename = "broadcast_ok")]
     BroadcastOk {
         /// The message id
         msg_id: MessageId,
@@ -156,13 +284,11 @@ pub enum BroadcastBody {
         in_reply_to: MessageId,
     },
     /// The data that communicates that the node needs to return its held messages
-    #[serde(rename = "read")]
     Read {
         /// The message id
         msg_id: MessageId,
     },
     /// The data that communicates all values that have been communicated with the node
-    #[serde(rename = "read_ok")]
     ReadOk {
         /// The message id
         msg_id: MessageId,
@@ -172,7 +298,6 @@ pub enum BroadcastBody {
         messages: HashSet<usize>,
     },
     /// The data that communicates the topology of the cluster
-    #[serde(rename = "topology")]
     Topology {
         /// The message id
         msg_id: MessageId,
@@ -180,7 +305,6 @@ pub enum BroadcastBody {
         topology: HashMap<String, HashSet<String>>,
     },
     /// The data that communicates the topology of the cluster has been received by the node
-    #[serde(rename = "topology_ok")]
     TopologyOk {
         /// The message id
         msg_id: MessageId,
@@ -189,15 +313,37 @@ pub enum BroadcastBody {
     },
 }
 
-impl MessageBody for BroadcastBody {
-    fn update_msg_id(&mut self, id: MessageId) {
-        match self {
-            BroadcastBody::Broadcast { msg_id, .. }
-            | BroadcastBody::BroadcastOk { msg_id, .. }
-            | BroadcastBody::Read { msg_id }
-            | BroadcastBody::ReadOk { msg_id, .. }
-            | BroadcastBody::Topology { msg_id, .. }
-            | BroadcastBody::TopologyOk { msg_id, .. } => *msg_id = id,
-        }
-    }
+/* ------ G-Counter ------ */
+
+/// The message body type used in the grow-only counter problem
+#[derive(MessageBody, Debug, Clone, PartialEq, Eq)]
+pub enum GCounterBody {
+    /// The data that communicates that the counter needs to be increased
+    Add {
+        /// The message id
+        msg_id: MessageId,
+        /// The amount to add to the counter
+        delta: u64,
+    },
+    /// The data that communicates that the counter has been increased
+    AddOk {
+        /// The message id
+        msg_id: MessageId,
+        /// The id of the message that this is responding to
+        in_reply_to: MessageId,
+    },
+    /// The data that communicates that the node needs to return the counter's value
+    Read {
+        /// The message id
+        msg_id: MessageId,
+    },
+    /// The data that communicates the counter's value
+    ReadOk {
+        /// The message id
+        msg_id: MessageId,
+        /// The id of the message that this is responding to
+        in_reply_to: MessageId,
+        /// The counter's value
+        value: u64,
+    },
 }
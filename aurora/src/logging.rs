@@ -0,0 +1,82 @@
+use std::borrow::Cow;
+
+use serde::Deserialize;
+use tracing::{debug, Level};
+use tracing_subscriber::EnvFilter;
+
+use crate::MessageId;
+
+/// The environment variable used to filter logs. This uses the same syntax as `RUST_LOG`, e.g.
+/// `AURORA_LOG=debug` or `AURORA_LOG=aurora::messages=debug,info`. By default, only `info` and
+/// above are logged.
+pub const LOG_FILTER_VAR: &str = "AURORA_LOG";
+
+/// The environment variable used to select the log format. If this is set to `json`, every log
+/// line is a JSON object. Otherwise, logs are human-readable.
+pub const LOG_FORMAT_VAR: &str = "AURORA_LOG_FORMAT";
+
+/// The target of the events that are logged for every message that is sent or received. These
+/// events are logged at the debug level.
+pub const MESSAGE_TARGET: &str = "aurora::messages";
+
+/// Installs a `tracing` subscriber that writes to stderr, which is where Maelstrom expects logs.
+/// See `LOG_FILTER_VAR` and `LOG_FORMAT_VAR` for how the subscriber is configured.
+///
+/// If a subscriber has already been installed, this does nothing, so nodes that want a different
+/// subscriber can install their own instead.
+pub fn init_logging() {
+    let filter = EnvFilter::try_from_env(LOG_FILTER_VAR).unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    // An error means that a subscriber was already installed
+    let _ = match std::env::var(LOG_FORMAT_VAR) {
+        Ok(format) if format.eq_ignore_ascii_case("json") => builder.json().try_init(),
+        _ => builder.try_init(),
+    };
+}
+
+/// The parts of a message that are included in logs. These are pulled out of the raw JSON, so
+/// that it does not matter what body type the message has.
+#[derive(Deserialize, Debug)]
+struct MessageFields<'a> {
+    #[serde(borrow)]
+    src: Cow<'a, str>,
+    #[serde(borrow)]
+    dest: Cow<'a, str>,
+    #[serde(borrow)]
+    body: BodyFields<'a>,
+}
+
+/// The parts of a message body that are included in logs.
+#[derive(Deserialize, Debug)]
+struct BodyFields<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: Cow<'a, str>,
+    msg_id: Option<MessageId>,
+    in_reply_to: Option<MessageId>,
+}
+
+/// Logs a message at the debug level with its sender, recipient, type, and ids. The fields are
+/// only parsed out of the JSON if the event would be recorded.
+pub(crate) fn log_message(json: &[u8], text: &str) {
+    if !tracing::enabled!(target: MESSAGE_TARGET, Level::DEBUG) {
+        return;
+    }
+    match serde_json::from_slice::<MessageFields<'_>>(json) {
+        Ok(MessageFields { src, dest, body }) => debug!(
+            target: MESSAGE_TARGET,
+            %src,
+            %dest,
+            "type" = %body.kind,
+            msg_id = body.msg_id.map(|id| id.0),
+            in_reply_to = body.in_reply_to.map(|id| id.0),
+            "{text}"
+        ),
+        Err(_) => debug!(
+            target: MESSAGE_TARGET,
+            json = %String::from_utf8_lossy(json),
+            "{text}"
+        ),
+    }
+}
@@ -1,6 +1,13 @@
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 use crate::MessageBody;
 
@@ -11,7 +18,20 @@ use crate::MessageBody;
 #[serde(transparent)]
 pub struct MessageId(pub usize);
 
+/// Hands out message ids. The client and every clone of the node's `Sender` share one allocator,
+/// so every message id that a node uses is unique, no matter where it came from.
+///
+/// Ids increase monotonically, starting from zero.
+#[derive(Debug, Default, Clone)]
+pub struct IdAllocator {
+    next: Arc<AtomicUsize>,
+}
+
 /// The message type that is sent and recieved by the client.
+///
+/// Besides the source, destination, and body, the envelope keeps the id that Maelstrom assigns to
+/// every message it routes and any other fields that it carries. These are written back out when
+/// the message is sent, so forwarding a message does not lose them.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(bound = "B: MessageBody")]
 pub struct Message<B: MessageBody> {
@@ -21,22 +41,77 @@ pub struct Message<B: MessageBody> {
     pub dest: String,
     /// The body of the `Message`, contains most of the relative data to solution implementors
     pub body: B,
+    /// The id that Maelstrom's network gave the message, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    /// Any other fields in the envelope
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The messages that a handler wants the client to send. A handler might reply to the message it
+/// was given, forward it on to other nodes, do both, or do neither. The client sends the messages
+/// in the order that they were added to the outbox.
+///
+/// Because the outbox is returned from the handler, everything that a handler sends can be checked
+/// by calling the handler directly, which makes nodes easy to unit test.
+///
+/// Before a message is sent, the client stamps it with a fresh message id (unless it was added with
+/// `Outbox::push_with_id`). Replies should be made with `Message::into_response`, which fills in
+/// their `in_reply_to` field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outbox<B: MessageBody> {
+    // Each message is paired with whether it keeps its current message id
+    msgs: Vec<(Message<B>, bool)>,
 }
 
 impl<B: MessageBody> Message<B> {
+    /// Creates a message with nothing else in its envelope.
+    pub fn new(src: impl Into<String>, dest: impl Into<String>, body: B) -> Self {
+        Self {
+            src: src.into(),
+            dest: dest.into(),
+            body,
+            id: None,
+            extra: Map::new(),
+        }
+    }
+
     /// Turns a request message into a response message by mutating the data in-place.
     /// The given function takes a mutable reference to the message's current body, allowing the
-    /// function to mutate the body at will.
+    /// function to mutate the body at will. Afterwards, the body's `in_reply_to` field is set to
+    /// the request's message id, if the request had one.
     ///
-    /// NOTE: This method also swaps the `src` and `dest` fields of the messages. It is generally
-    /// recommended to only call this method only once (or an odd number of times). Otherwise, the
-    /// Maelstrom tests will fail.
+    /// NOTE: This method also swaps the `src` and `dest` fields of the messages and clears the rest
+    /// of the envelope, which belongs to the request. It is generally recommended to call this
+    /// method only once (or an odd number of times). Otherwise, the Maelstrom tests will fail.
     pub fn into_response<F>(&mut self, f: F)
     where
         F: FnOnce(&mut B),
     {
+        let msg_id = self.body.msg_id();
         std::mem::swap(&mut self.src, &mut self.dest);
-        f(&mut self.body)
+        self.id = None;
+        self.extra.clear();
+        f(&mut self.body);
+        if let Some(msg_id) = msg_id {
+            self.body.update_in_reply_to(msg_id);
+        }
+    }
+
+    /// Replaces the message's body using the given function, keeping the rest of the envelope.
+    pub fn map_body<C, F>(self, f: F) -> Message<C>
+    where
+        C: MessageBody,
+        F: FnOnce(B) -> C,
+    {
+        Message {
+            src: self.src,
+            dest: self.dest,
+            body: f(self.body),
+            id: self.id,
+            extra: self.extra,
+        }
     }
 
     /// Creates a copy of the message but uses a new message id in the body
@@ -47,6 +122,125 @@ impl<B: MessageBody> Message<B> {
     }
 }
 
+impl IdAllocator {
+    /// Creates a new allocator. Its ids are independent of every other allocator's ids.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the next message id.
+    pub fn next_id(&self) -> MessageId {
+        MessageId(self.next.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl<B: MessageBody> Outbox<B> {
+    /// Creates an empty outbox.
+    pub fn new() -> Self {
+        Self { msgs: Vec::new() }
+    }
+
+    /// Adds a message to the end of the outbox. The client stamps it with a fresh message id.
+    pub fn push(&mut self, msg: Message<B>) {
+        self.msgs.push((msg, false))
+    }
+
+    /// Adds a message to the end of the outbox that is sent with the message id that it already
+    /// has. This is meant for messages whose ids the node tracks, like retransmissions. These ids
+    /// should come from the client's `IdAllocator` (see `Sender::next_id`).
+    pub fn push_with_id(&mut self, msg: Message<B>) {
+        self.msgs.push((msg, true))
+    }
+
+    /// Replaces the body of every message in the outbox using the given function. Whether each
+    /// message keeps its message id is unchanged.
+    pub fn map_bodies<C, F>(self, f: F) -> Outbox<C>
+    where
+        C: MessageBody,
+        F: Fn(B) -> C,
+    {
+        let msgs = self.msgs.into_iter();
+        Outbox {
+            msgs: msgs
+                .map(|(msg, keep_id)| (msg.map_body(&f), keep_id))
+                .collect(),
+        }
+    }
+
+    /// Returns the number of messages in the outbox.
+    pub fn len(&self) -> usize {
+        self.msgs.len()
+    }
+
+    /// Returns if the outbox has no messages.
+    pub fn is_empty(&self) -> bool {
+        self.msgs.is_empty()
+    }
+
+    /// Returns an iterator over the messages in the order that they will be sent.
+    pub fn iter(&self) -> impl Iterator<Item = &Message<B>> {
+        self.msgs.iter().map(|(msg, _)| msg)
+    }
+
+    /// Returns the messages in the order that they will be sent and whether each of them keeps
+    /// its current message id.
+    pub(crate) fn into_parts(self) -> impl Iterator<Item = (Message<B>, bool)> {
+        self.msgs.into_iter()
+    }
+}
+
+impl<B: MessageBody> Default for Outbox<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: MessageBody> From<Message<B>> for Outbox<B> {
+    fn from(msg: Message<B>) -> Self {
+        Self {
+            msgs: vec![(msg, false)],
+        }
+    }
+}
+
+impl<B: MessageBody> From<Option<Message<B>>> for Outbox<B> {
+    fn from(msg: Option<Message<B>>) -> Self {
+        msg.into_iter().collect()
+    }
+}
+
+impl<B: MessageBody> From<Vec<Message<B>>> for Outbox<B> {
+    fn from(msgs: Vec<Message<B>>) -> Self {
+        msgs.into_iter().collect()
+    }
+}
+
+impl<B: MessageBody> FromIterator<Message<B>> for Outbox<B> {
+    fn from_iter<I: IntoIterator<Item = Message<B>>>(iter: I) -> Self {
+        let mut digest = Self::new();
+        digest.extend(iter);
+        digest
+    }
+}
+
+impl<B: MessageBody> Extend<Message<B>> for Outbox<B> {
+    fn extend<I: IntoIterator<Item = Message<B>>>(&mut self, iter: I) {
+        self.msgs.extend(iter.into_iter().map(|msg| (msg, false)))
+    }
+}
+
+impl<B: MessageBody> IntoIterator for Outbox<B> {
+    type Item = Message<B>;
+    type IntoIter = std::iter::Map<
+        std::vec::IntoIter<(Message<B>, bool)>,
+        fn((Message<B>, bool)) -> Message<B>,
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.msgs.into_iter().map(|(msg, _)| msg)
+    }
+}
+
 impl From<usize> for MessageId {
     fn from(value: usize) -> Self {
         MessageId(value)
@@ -1,30 +1,277 @@
-use tokio::sync::mpsc::UnboundedSender;
+use std::{
+    future::{ready, Future},
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::{Message, MessageBody, MessageId};
+use serde_json::Value;
+
+use crate::{ErrorBody, Message, MessageBody, NodeContext, Outbox, TimerToken};
 
 /// The main trait which is used to model a node.
+///
+/// Every `Node` is also an `AsyncNode`, which is the trait that the client actually drives.
 pub trait Node: Sized {
     /// The message type that this node expects to communicate
     type Body: MessageBody;
 
-    /// Creates a new node from the data contained in an `Init` message
-    fn init(
-        sender: UnboundedSender<Message<Self::Body>>,
-        node_id: String,
-        node_ids: Vec<String>,
-    ) -> Self;
+    /// Creates a new node from the data contained in an `Init` message. The context holds the
+    /// node's id and the ids of the rest of the cluster.
+    fn init(ctx: &NodeContext<Self::Body>) -> Self;
 
-    /// Retrieves the next `MessageId` from the node.
+    /// The main method used to process messages that the client receives. The returned outbox can
+    /// contain any number of messages, e.g. a reply to the message and the messages that forward
+    /// it to other nodes. The client sends them in order.
     ///
-    /// NOTE: Nodes should never create the same message id twice.
-    fn next_id(&mut self) -> MessageId;
+    /// NOTE: Messages that the client receives via the channel are not passed through this method.
+    /// Neither are replies to messages that were sent using `Sender::rpc`; those are routed to the
+    /// future returned by that method.
+    fn handle_msg(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> anyhow::Result<Outbox<Self::Body>>;
+
+    /// Called by the client for messages whose body has a type that the node's body type does not
+    /// know. The message's body is the raw JSON, so the node can log it, forward it, or reply to it
+    /// with whatever it wants. By default, the client replies with a `not-supported` error.
+    fn handle_unknown(
+        &mut self,
+        _ctx: &NodeContext<Self::Body>,
+        msg: Message<Value>,
+    ) -> anyhow::Result<Outbox<Value>> {
+        Err(ErrorBody::unknown_type(&msg.body).into())
+    }
+
+    /// The interval at which the client calls `on_tick`. This is checked once, right after the
+    /// node is constructed. By default, nodes do not tick.
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called by the client every tick interval. This is meant for periodic work, like
+    /// retransmissions and gossip. The client sends the messages in the returned outbox.
+    fn on_tick(&mut self, _ctx: &NodeContext<Self::Body>) -> anyhow::Result<Outbox<Self::Body>> {
+        Ok(Outbox::new())
+    }
+
+    /// Called by the client when a timer scheduled through `NodeContext::schedule` fires. The
+    /// client sends the messages in the returned outbox.
+    fn on_timer(
+        &mut self,
+        _ctx: &NodeContext<Self::Body>,
+        _token: TimerToken,
+    ) -> anyhow::Result<Outbox<Self::Body>> {
+        Ok(Outbox::new())
+    }
+
+    /// Called by the client once it stops receiving messages, either because the input was closed
+    /// or because the process was asked to terminate. Messages sent through the sender during this
+    /// method are still sent, but replies to RPCs will never arrive.
+    fn shutdown(&mut self, _ctx: &NodeContext<Self::Body>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// The async variant of the `Node` trait. This is the trait that the client drives, and it is
+/// implemented for every `Node` (that is `Send`).
+///
+/// While `handle_msg` is running, the client continues to send messages from the channel and to
+/// route replies to outstanding RPCs. This means that a handler can await the reply to an RPC.
+/// Other inbound messages are held until the handler finishes.
+///
+/// NOTE: `init` is called before the client starts processing messages, so awaiting an RPC in
+/// `init` will never finish.
+pub trait AsyncNode: Sized + Send {
+    /// The message type that this node expects to communicate
+    type Body: MessageBody;
 
-    /// The main method used to process messages that the client receives. This method is not
-    /// required to return response message.
+    /// Creates a new node from the data contained in an `Init` message
+    fn init(ctx: &NodeContext<Self::Body>) -> impl Future<Output = Self> + Send;
+
+    /// The main method used to process messages that the client receives. See `Node::handle_msg`.
     ///
-    /// NOTE: Messages that the client receives via the channel are not passed through this method.
+    /// NOTE: Like with `Node`, messages from the channel and replies to RPCs are not passed
+    /// through this method.
+    fn handle_msg(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send;
+
+    /// Called by the client for messages with unknown body types. See `Node::handle_unknown`.
+    fn handle_unknown(
+        &mut self,
+        _ctx: &NodeContext<Self::Body>,
+        msg: Message<Value>,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Value>>> + Send {
+        ready(Err(ErrorBody::unknown_type(&msg.body).into()))
+    }
+
+    /// The interval at which the client calls `on_tick`. See `Node::tick_interval`.
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called by the client every tick interval. See `Node::on_tick`.
+    fn on_tick(
+        &mut self,
+        _ctx: &NodeContext<Self::Body>,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send {
+        ready(Ok(Outbox::new()))
+    }
+
+    /// Called by the client when a timer fires. See `Node::on_timer`.
+    fn on_timer(
+        &mut self,
+        _ctx: &NodeContext<Self::Body>,
+        _token: TimerToken,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send {
+        ready(Ok(Outbox::new()))
+    }
+
+    /// Called by the client when it shuts down. See `Node::shutdown`.
+    fn shutdown(
+        &mut self,
+        _ctx: &NodeContext<Self::Body>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send {
+        ready(Ok(()))
+    }
+}
+
+/// A node whose handlers only need shared access to its state. These nodes are driven by
+/// `main_loop_concurrent`, which runs the handlers for independent inputs as separate tasks. This
+/// allows a handler that is awaiting an RPC to overlap with the handling of other messages.
+///
+/// Any state that is modified by handlers needs to be behind some kind of lock (or be atomic).
+/// Handlers can run in any order, including ticks and timers, so they should not assume that
+/// inputs are handled in the order that they arrive.
+pub trait ConcurrentNode: Sized + Send + Sync + 'static {
+    /// The message type that this node expects to communicate
+    type Body: MessageBody;
+
+    /// Creates a new node from the data contained in an `Init` message
+    fn init(ctx: &NodeContext<Self::Body>) -> impl Future<Output = Self> + Send;
+
+    /// The main method used to process messages that the client receives. See
+    /// `AsyncNode::handle_msg`.
+    fn handle_msg(
+        &self,
+        ctx: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send;
+
+    /// Called by the client for messages with unknown body types. See `Node::handle_unknown`.
+    fn handle_unknown(
+        &self,
+        _ctx: &NodeContext<Self::Body>,
+        msg: Message<Value>,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Value>>> + Send {
+        ready(Err(ErrorBody::unknown_type(&msg.body).into()))
+    }
+
+    /// The interval at which the client calls `on_tick`. See `Node::tick_interval`.
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called by the client every tick interval. See `Node::on_tick`.
+    fn on_tick(
+        &self,
+        _ctx: &NodeContext<Self::Body>,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send {
+        ready(Ok(Outbox::new()))
+    }
+
+    /// Called by the client when a timer fires. See `Node::on_timer`.
+    fn on_timer(
+        &self,
+        _ctx: &NodeContext<Self::Body>,
+        _token: TimerToken,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send {
+        ready(Ok(Outbox::new()))
+    }
+
+    /// Called by the client when it shuts down, after every running handler has finished. See
+    /// `Node::shutdown`.
+    fn shutdown(
+        &self,
+        _ctx: &NodeContext<Self::Body>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send {
+        ready(Ok(()))
+    }
+}
+
+/// Wraps a `ConcurrentNode` so that it can be constructed by the client like any other node. The
+/// concurrent main loop never calls the handlers through this wrapper.
+pub(crate) struct SharedNode<N>(pub(crate) Arc<N>);
+
+impl<N: Node + Send> AsyncNode for N {
+    type Body = <N as Node>::Body;
+
+    fn init(ctx: &NodeContext<Self::Body>) -> impl Future<Output = Self> + Send {
+        ready(<N as Node>::init(ctx))
+    }
+
     fn handle_msg(
         &mut self,
+        ctx: &NodeContext<Self::Body>,
         msg: Message<Self::Body>,
-    ) -> anyhow::Result<Option<Message<Self::Body>>>;
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send {
+        ready(<N as Node>::handle_msg(self, ctx, msg))
+    }
+
+    fn handle_unknown(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+        msg: Message<Value>,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Value>>> + Send {
+        ready(<N as Node>::handle_unknown(self, ctx, msg))
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        <N as Node>::tick_interval(self)
+    }
+
+    fn on_tick(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send {
+        ready(<N as Node>::on_tick(self, ctx))
+    }
+
+    fn on_timer(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+        token: TimerToken,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send {
+        ready(<N as Node>::on_timer(self, ctx, token))
+    }
+
+    fn shutdown(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send {
+        ready(<N as Node>::shutdown(self, ctx))
+    }
+}
+
+impl<N: ConcurrentNode> AsyncNode for SharedNode<N> {
+    type Body = N::Body;
+
+    async fn init(ctx: &NodeContext<Self::Body>) -> Self {
+        SharedNode(Arc::new(N::init(ctx).await))
+    }
+
+    fn handle_msg(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> impl Future<Output = anyhow::Result<Outbox<Self::Body>>> + Send {
+        self.0.handle_msg(ctx, msg)
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        self.0.tick_interval()
+    }
 }
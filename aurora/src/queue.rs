@@ -0,0 +1,203 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::Notify;
+
+use crate::{MessageBody, Outbound, OverflowPolicy, RpcError};
+
+/// Statistics about the queue of messages that nodes send through their `Sender`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    /// The number of messages that are waiting to be sent
+    pub depth: usize,
+    /// The largest that `depth` has been
+    pub max_depth: usize,
+    /// The number of messages that were dropped to make room for newer ones
+    pub dropped: u64,
+    /// The number of messages that were not queued because the queue was full
+    pub rejected: u64,
+}
+
+/// The queue between the node's `Sender` and the client. The queue can be bounded (see
+/// `ClientConfig::outbound_capacity`), in which case its `OverflowPolicy` decides what happens to
+/// messages that do not fit.
+///
+/// Only messages count against the capacity. Timers are always queued, and they are never dropped.
+#[derive(Debug, Clone)]
+pub(crate) struct QueueSender<B: MessageBody> {
+    shared: Arc<Shared<B>>,
+}
+
+/// The client's end of the outbound queue. The client's context holds a sender for as long as the
+/// client runs, so the receiver never sees the queue disconnect.
+#[derive(Debug)]
+pub(crate) struct QueueReceiver<B: MessageBody> {
+    shared: Arc<Shared<B>>,
+}
+
+#[derive(Debug)]
+struct Shared<B: MessageBody> {
+    state: Mutex<State<B>>,
+    /// Notified when something is pushed
+    readable: Notify,
+    /// Notified when a message is taken out or the receiver is dropped
+    writable: Notify,
+    capacity: Option<usize>,
+    overflow: OverflowPolicy,
+}
+
+#[derive(Debug)]
+struct State<B: MessageBody> {
+    items: VecDeque<Outbound<B>>,
+    closed: bool,
+    stats: QueueStats,
+}
+
+/// Creates a new outbound queue.
+pub(crate) fn queue<B: MessageBody>(
+    capacity: Option<usize>,
+    overflow: OverflowPolicy,
+) -> (QueueSender<B>, QueueReceiver<B>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            closed: false,
+            stats: QueueStats::default(),
+        }),
+        readable: Notify::new(),
+        writable: Notify::new(),
+        capacity,
+        overflow,
+    });
+    let recv = QueueReceiver {
+        shared: shared.clone(),
+    };
+    (QueueSender { shared }, recv)
+}
+
+impl<B: MessageBody> QueueSender<B> {
+    /// Queues an item without waiting. If the queue is full, the overflow policy is applied, but
+    /// `OverflowPolicy::Await` is treated like `OverflowPolicy::Error`.
+    pub(crate) fn try_push(&self, out: Outbound<B>) -> Result<(), RpcError> {
+        self.push_now(out, false).map_err(|(_, err)| err)
+    }
+
+    /// Queues an item. If the queue is full and the overflow policy is `OverflowPolicy::Await`,
+    /// this waits until there is room. Otherwise, the policy is applied.
+    pub(crate) async fn push(&self, mut out: Outbound<B>) -> Result<(), RpcError> {
+        let waits = self.shared.overflow == OverflowPolicy::Await;
+        loop {
+            let writable = self.shared.writable.notified();
+            tokio::pin!(writable);
+            // Register for a wakeup before checking, so that a message taken out in between is not
+            // missed
+            writable.as_mut().enable();
+            match self.push_now(out, waits) {
+                Ok(()) => return Ok(()),
+                Err((rejected, RpcError::Full)) if waits => {
+                    out = *rejected;
+                    writable.await;
+                }
+                Err((_, err)) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns the queue's current statistics.
+    pub(crate) fn stats(&self) -> QueueStats {
+        self.shared.state.lock().unwrap().stats
+    }
+
+    /// Queues an item without waiting. If it could not be queued, it is handed back so that it can
+    /// be retried. Callers that will wait for room skip the overflow policy.
+    fn push_now(
+        &self,
+        out: Outbound<B>,
+        waiting: bool,
+    ) -> Result<(), (Box<Outbound<B>>, RpcError)> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Err((Box::new(out), RpcError::Disconnected));
+        }
+        let counts = !matches!(out, Outbound::Timer(..));
+        let full = self
+            .shared
+            .capacity
+            .is_some_and(|cap| state.stats.depth >= cap);
+        if counts && full && waiting {
+            return Err((Box::new(out), RpcError::Full));
+        }
+        if counts && full {
+            match self.shared.overflow {
+                OverflowPolicy::DropOldest => {
+                    // Timers and RPCs are never dropped, and a capacity of zero means that there
+                    // is nothing to drop
+                    let Some(oldest) = state
+                        .items
+                        .iter()
+                        .position(|out| matches!(out, Outbound::Message(_)))
+                    else {
+                        state.stats.rejected += 1;
+                        return Err((Box::new(out), RpcError::Full));
+                    };
+                    state.items.remove(oldest);
+                    state.stats.depth -= 1;
+                    state.stats.dropped += 1;
+                }
+                OverflowPolicy::Await | OverflowPolicy::Error => {
+                    state.stats.rejected += 1;
+                    return Err((Box::new(out), RpcError::Full));
+                }
+            }
+        }
+        if counts {
+            state.stats.depth += 1;
+            state.stats.max_depth = state.stats.max_depth.max(state.stats.depth);
+        }
+        state.items.push_back(out);
+        drop(state);
+        self.shared.readable.notify_one();
+        Ok(())
+    }
+}
+
+impl<B: MessageBody> QueueReceiver<B> {
+    /// Waits for the next item. This is cancellation safe.
+    pub(crate) async fn recv(&mut self) -> Outbound<B> {
+        loop {
+            let readable = self.shared.readable.notified();
+            tokio::pin!(readable);
+            readable.as_mut().enable();
+            match self.try_recv() {
+                Some(out) => return out,
+                None => readable.await,
+            }
+        }
+    }
+
+    /// Takes the next item out of the queue without waiting.
+    pub(crate) fn try_recv(&self) -> Option<Outbound<B>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let out = state.items.pop_front()?;
+        if !matches!(out, Outbound::Timer(..)) {
+            state.stats.depth -= 1;
+            drop(state);
+            self.shared.writable.notify_one();
+        }
+        Some(out)
+    }
+}
+
+impl<B: MessageBody> Drop for QueueReceiver<B> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        // Dropping the queued RPCs lets their futures know that they will never get a reply
+        let items = std::mem::take(&mut state.items);
+        drop(state);
+        drop(items);
+        self.shared.writable.notify_waiters();
+    }
+}
@@ -0,0 +1,243 @@
+use std::{collections::HashMap, time::Duration};
+
+use rand::Rng;
+use tokio::time::Instant;
+
+use crate::{Message, MessageBody, MessageId, NodeContext, Outbox, RpcError};
+
+/// How often and for how long a `Reliable` tracker retransmits a message that has not been
+/// replied to. `KvClient::update` uses the same policy for retrying compare-and-sets.
+///
+/// The first retransmission happens `initial` after the message was sent. Every retransmission
+/// after that waits `multiplier` times as long as the one before it, up to `max_backoff`. A random
+/// delay of up to `jitter` is added to each wait so that nodes that lost messages at the same time
+/// do not all retransmit at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How long to wait for a reply before the first retransmission
+    pub initial: Duration,
+    /// The longest that the tracker will wait between retransmissions
+    pub max_backoff: Duration,
+    /// How much longer each wait is than the one before it
+    pub multiplier: u32,
+    /// The most random delay that is added to each wait
+    pub jitter: Duration,
+    /// How long after the message was first sent to give up on it. By default, messages are
+    /// retransmitted until they are replied to.
+    pub deadline: Option<Duration>,
+}
+
+/// Tracks outstanding requests and retransmits them until they are replied to. This is useful
+/// for messages that need to arrive in spite of network partitions, like gossip.
+///
+/// The tracker does not keep time on its own. Instead, the node calls `Reliable::poll` from one of
+/// its handlers (usually `on_tick`) to get the messages that need to be retransmitted and the ones
+/// that were given up on. Replies are passed to `Reliable::ack` from `handle_msg`. A reply matches
+/// a request if its `in_reply_to` is the request's message id and it comes from the node that the
+/// request was sent to.
+#[derive(Debug)]
+pub struct Reliable<B: MessageBody> {
+    policy: RetryPolicy,
+    outstanding: HashMap<MessageId, Outstanding<B>>,
+}
+
+/// A request that has not been replied to yet.
+#[derive(Debug)]
+struct Outstanding<B: MessageBody> {
+    msg: Message<B>,
+    attempts: u32,
+    /// How long to wait after the next retransmission
+    backoff: Duration,
+    /// When to retransmit next
+    resend_at: Instant,
+    /// When to give up, if ever
+    expires_at: Option<Instant>,
+}
+
+/// What became of a request that a `Reliable` tracker was tracking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery<B: MessageBody> {
+    /// A matching reply arrived
+    Delivered {
+        /// The request that was replied to
+        request: Message<B>,
+        /// The number of times that the request was sent
+        attempts: u32,
+    },
+    /// The deadline passed before a matching reply arrived
+    Expired {
+        /// The request that was given up on
+        request: Message<B>,
+        /// The number of times that the request was sent
+        attempts: u32,
+    },
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(150),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2,
+            jitter: Duration::from_millis(50),
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns how long to wait before the next attempt, given the current backoff, along with the
+    /// backoff for the attempt after that.
+    pub(crate) fn wait<B: MessageBody>(
+        &self,
+        ctx: &NodeContext<B>,
+        backoff: Duration,
+    ) -> (Duration, Duration) {
+        let jitter = if self.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            ctx.rng().gen_range(Duration::ZERO..=self.jitter)
+        };
+        let next = backoff
+            .saturating_mul(self.multiplier)
+            .min(self.max_backoff);
+        (backoff + jitter, next)
+    }
+}
+
+impl<B: MessageBody> Reliable<B> {
+    /// Creates a tracker that retransmits according to the given policy.
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            outstanding: HashMap::new(),
+        }
+    }
+
+    /// Stamps a fresh message id on the message and starts tracking it. The returned message
+    /// needs to be sent by the node, either with `Outbox::push_with_id` or with
+    /// `Sender::send_with_id`, so that the tracked id is kept.
+    ///
+    /// The reply is matched using the message id, so the body must be able to hold one. If it
+    /// can't, no id is used up and `RpcError::MissingMsgId` is returned.
+    pub fn track(
+        &mut self,
+        ctx: &NodeContext<B>,
+        mut msg: Message<B>,
+    ) -> Result<Message<B>, RpcError> {
+        if msg.body.msg_id().is_none() {
+            return Err(RpcError::MissingMsgId);
+        }
+        let id = ctx.next_id();
+        msg.body.update_msg_id(id);
+        let now = ctx.now();
+        let mut outstanding = Outstanding {
+            msg: msg.clone(),
+            attempts: 1,
+            backoff: self.policy.initial,
+            resend_at: now,
+            expires_at: self.policy.deadline.map(|deadline| now + deadline),
+        };
+        outstanding.sent(ctx, &self.policy, now);
+        self.outstanding.insert(id, outstanding);
+        Ok(msg)
+    }
+
+    /// The same as `Reliable::track`, but the message is sent through the context's sender.
+    pub fn send(&mut self, ctx: &NodeContext<B>, msg: Message<B>) -> Result<MessageId, RpcError> {
+        let msg = self.track(ctx, msg)?;
+        let id = msg.body.msg_id().ok_or(RpcError::MissingMsgId)?;
+        if let Err(err) = ctx.sender().send_with_id(msg) {
+            self.outstanding.remove(&id);
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    /// Stops tracking the request that the given message replies to, if there is one. Messages
+    /// that do not reply to a tracked request are ignored.
+    pub fn ack(&mut self, reply: &Message<B>) -> Option<Delivery<B>> {
+        let id = reply.body.in_reply_to()?;
+        if self.outstanding.get(&id)?.msg.dest != reply.src {
+            return None;
+        }
+        let Outstanding { msg, attempts, .. } = self.outstanding.remove(&id)?;
+        Some(Delivery::Delivered {
+            request: msg,
+            attempts,
+        })
+    }
+
+    /// Stops tracking a request without waiting for its reply. The request is returned if it was
+    /// being tracked.
+    pub fn cancel(&mut self, id: MessageId) -> Option<Message<B>> {
+        self.outstanding.remove(&id).map(|out| out.msg)
+    }
+
+    /// Returns the requests that are due to be retransmitted and reports the ones whose deadlines
+    /// have passed. The retransmissions keep their message ids, so they need to be sent with
+    /// `Outbox::push_with_id` or `Sender::send_with_id`.
+    pub fn poll(&mut self, ctx: &NodeContext<B>) -> (Outbox<B>, Vec<Delivery<B>>) {
+        let now = ctx.now();
+        let expired = self
+            .outstanding
+            .iter()
+            .filter(|(_, out)| out.expires_at.is_some_and(|at| at <= now))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let expired = expired
+            .into_iter()
+            .filter_map(|id| self.outstanding.remove(&id))
+            .map(|Outstanding { msg, attempts, .. }| Delivery::Expired {
+                request: msg,
+                attempts,
+            })
+            .collect();
+        let mut outbox = Outbox::new();
+        for out in self.outstanding.values_mut() {
+            if out.resend_at <= now {
+                out.attempts += 1;
+                out.sent(ctx, &self.policy, now);
+                outbox.push_with_id(out.msg.clone());
+            }
+        }
+        (outbox, expired)
+    }
+
+    /// Returns when `Reliable::poll` next has something to do, which is useful for nodes that
+    /// schedule timers instead of ticking.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        self.outstanding
+            .values()
+            .map(|out| match out.expires_at {
+                Some(at) => at.min(out.resend_at),
+                None => out.resend_at,
+            })
+            .min()
+    }
+
+    /// Returns the number of requests that have not been replied to.
+    pub fn len(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Returns whether every tracked request has been replied to.
+    pub fn is_empty(&self) -> bool {
+        self.outstanding.is_empty()
+    }
+}
+
+impl<B: MessageBody> Default for Reliable<B> {
+    fn default() -> Self {
+        Self::new(RetryPolicy::default())
+    }
+}
+
+impl<B: MessageBody> Outstanding<B> {
+    /// Schedules the next retransmission after the request was sent at the given time.
+    fn sent(&mut self, ctx: &NodeContext<B>, policy: &RetryPolicy, now: Instant) {
+        let (wait, backoff) = policy.wait(ctx, self.backoff);
+        self.resend_at = now + wait;
+        self.backoff = backoff;
+    }
+}
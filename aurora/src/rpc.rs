@@ -0,0 +1,235 @@
+use std::{
+    fmt::Display,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{sync::oneshot, time::Instant};
+
+use tokio::task::JoinHandle;
+
+use crate::{
+    ErrorBody, Holds, IdAllocator, Message, MessageBody, MessageId, QueueSender, QueueStats,
+    TaskSet, TimerToken,
+};
+
+/// The handle that nodes use to send messages through the client. Messages can either be sent
+/// and forgotten about or sent as an RPC, in which case the client will route the reply to the
+/// returned future instead of passing it to the node's `handle_msg` method.
+///
+/// Every message that is sent through the sender is stamped with a fresh message id from the
+/// client's `IdAllocator`. The only exception is `Sender::send_with_id`, which is meant for
+/// messages whose ids the node tracks.
+///
+/// The sender is also used to schedule one-shot timers and to spawn background tasks that the
+/// client cancels when it shuts down.
+///
+/// Messages wait in a queue until the client sends them. If the queue is bounded and full, what
+/// happens depends on the `OverflowPolicy` (see `ClientConfig::outbound_capacity`). The only
+/// method that can wait for room is `Sender::send_wait`, which is how nodes apply backpressure
+/// under `OverflowPolicy::Await`.
+#[derive(Debug, Clone)]
+pub struct Sender<B: MessageBody> {
+    send: QueueSender<B>,
+    tasks: TaskSet,
+    ids: IdAllocator,
+}
+
+/// The messages that flow from the `Sender` to the client.
+#[derive(Debug)]
+pub(crate) enum Outbound<B: MessageBody> {
+    /// A message that needs to be sent
+    Message(Message<B>),
+    /// A message that needs to be sent and whose reply needs to be routed back to the waiter
+    Rpc(Message<B>, Waiter<B>),
+    /// A timer that needs to be scheduled
+    Timer(Instant, TimerToken),
+}
+
+/// The channel used to pass the reply to an RPC back to its future.
+pub(crate) type ReplySender<B> = oneshot::Sender<Result<Message<B>, ErrorBody>>;
+
+/// Reads the body of the reply to an RPC from its raw JSON.
+pub(crate) type ParseReply<B> = fn(&str) -> serde_json::Result<B>;
+
+/// The future that is waiting for the reply to an RPC.
+#[derive(Debug)]
+pub(crate) struct Waiter<B: MessageBody> {
+    pub(crate) reply: ReplySender<B>,
+    /// How the reply's body is read, if it should not be read like every other body
+    pub(crate) parse: Option<ParseReply<B>>,
+}
+
+/// The errors that can occur while sending a message through a `Sender`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// The client has hung up, so no messages can be sent or replies received
+    Disconnected,
+    /// The RPC body does not have a message id, so its reply can not be identified
+    MissingMsgId,
+    /// The recipient of the RPC replied with an error
+    Remote(ErrorBody),
+    /// The outbound queue is full, so the message was not sent
+    Full,
+}
+
+/// The future returned by `Sender::rpc`. It resolves to the message whose `in_reply_to` field
+/// matches the message id of the request. If that message is an error, `RpcError::Remote` is
+/// returned instead.
+#[derive(Debug)]
+pub struct RpcResponse<B: MessageBody> {
+    inner: Result<oneshot::Receiver<Result<Message<B>, ErrorBody>>, Option<RpcError>>,
+}
+
+impl<B: MessageBody> Sender<B> {
+    pub(crate) fn new(send: QueueSender<B>, tasks: TaskSet, ids: IdAllocator) -> Self {
+        Self { send, tasks, ids }
+    }
+
+    /// Returns the next message id from the client's allocator.
+    pub fn next_id(&self) -> MessageId {
+        self.ids.next_id()
+    }
+
+    /// Returns a handle to the client's id allocator.
+    pub fn ids(&self) -> IdAllocator {
+        self.ids.clone()
+    }
+
+    /// Stamps a fresh message id on the message and sends it to the client to be sent over
+    /// stdout. The id that was used is returned so that the message can be tracked.
+    pub fn send(&self, mut msg: Message<B>) -> Result<MessageId, RpcError> {
+        let id = self.next_id();
+        msg.body.update_msg_id(id);
+        self.send_with_id(msg).map(|_| id)
+    }
+
+    /// Sends a message to the client to be sent over stdout without changing its message id. This
+    /// is meant for messages whose ids the node tracks, like retransmissions. These ids should come
+    /// from `Sender::next_id`.
+    pub fn send_with_id(&self, msg: Message<B>) -> Result<(), RpcError> {
+        self.send.try_push(Outbound::Message(msg))
+    }
+
+    /// The same as `Sender::send`, but if the outbound queue is full and the overflow policy is
+    /// `OverflowPolicy::Await`, this waits until there is room.
+    pub async fn send_wait(&self, mut msg: Message<B>) -> Result<MessageId, RpcError> {
+        let id = self.next_id();
+        msg.body.update_msg_id(id);
+        self.send.push(Outbound::Message(msg)).await.map(|_| id)
+    }
+
+    /// Returns statistics about the outbound queue.
+    pub fn queue_stats(&self) -> QueueStats {
+        self.send.stats()
+    }
+
+    /// Stamps a fresh message id on the message, sends it to the client to be sent over stdout, and
+    /// returns a future that resolves to its reply. The reply is matched using the stamped message
+    /// id, so the body must be able to hold one.
+    ///
+    /// NOTE: Dropping the returned future does not stop the message from being sent. If the reply
+    /// arrives after the future has been dropped, it is discarded.
+    pub fn rpc(&self, msg: Message<B>) -> RpcResponse<B> {
+        self.push_rpc(msg, None)
+    }
+
+    /// The same as `Sender::rpc`, but the reply is read as a `T` and then wrapped in the node's
+    /// body type. This is for talking to services whose body types share types with the node's
+    /// other bodies (e.g. `read_ok`). The reply is only read as one of the node's other bodies if
+    /// it is not a `T`.
+    pub fn rpc_as<T: MessageBody, I>(&self, msg: Message<B>) -> RpcResponse<B>
+    where
+        B: Holds<T, I>,
+    {
+        self.push_rpc(msg, Some(parse_as::<T, B, I>))
+    }
+
+    /// The same as `Sender::rpc_as`, but the reply is read with the given parser.
+    pub(crate) fn rpc_with(&self, msg: Message<B>, parse: ParseReply<B>) -> RpcResponse<B> {
+        self.push_rpc(msg, Some(parse))
+    }
+
+    /// Schedules a one-shot timer. Once the given duration has elapsed, the node's `on_timer`
+    /// method is called with the given token. For `Node`s and `AsyncNode`s, timers are delivered
+    /// like messages, one input at a time, so they never run at the same time as the node's other
+    /// handlers. A `ConcurrentNode`'s timers are handled on their own tasks, so they can run at the
+    /// same time as its other handlers.
+    pub fn schedule(&self, after: Duration, token: TimerToken) -> Result<(), RpcError> {
+        self.send
+            .try_push(Outbound::Timer(Instant::now() + after, token))
+    }
+
+    /// Spawns a background task onto the tokio runtime. Unlike tasks spawned with `tokio::spawn`,
+    /// the client cancels these tasks when it shuts down, after the node's `shutdown` method has
+    /// been called.
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tasks.spawn(fut)
+    }
+}
+
+impl<B: MessageBody> Sender<B> {
+    /// Stamps a fresh message id on an RPC and queues it along with its waiter.
+    fn push_rpc(&self, mut msg: Message<B>, parse: Option<ParseReply<B>>) -> RpcResponse<B> {
+        msg.body.update_msg_id(self.next_id());
+        if msg.body.msg_id().is_none() {
+            return RpcResponse::failed(RpcError::MissingMsgId);
+        }
+        let (reply, recv) = oneshot::channel();
+        match self
+            .send
+            .try_push(Outbound::Rpc(msg, Waiter { reply, parse }))
+        {
+            Ok(()) => RpcResponse { inner: Ok(recv) },
+            Err(err) => RpcResponse::failed(err),
+        }
+    }
+}
+
+/// Reads a body as a `T` and wraps it in a body type that holds it.
+pub(crate) fn parse_as<T: MessageBody, B: Holds<T, I>, I>(body: &str) -> serde_json::Result<B> {
+    serde_json::from_str(body).map(B::wrap)
+}
+
+impl<B: MessageBody> RpcResponse<B> {
+    fn failed(err: RpcError) -> Self {
+        Self {
+            inner: Err(Some(err)),
+        }
+    }
+}
+
+impl<B: MessageBody> Future for RpcResponse<B> {
+    type Output = Result<Message<B>, RpcError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut self.get_mut().inner {
+            Ok(recv) => Pin::new(recv).poll(cx).map(|resp| match resp {
+                Ok(resp) => resp.map_err(RpcError::Remote),
+                Err(_) => Err(RpcError::Disconnected),
+            }),
+            Err(err) => Poll::Ready(Err(err
+                .take()
+                .expect("RpcResponse polled after completion"))),
+        }
+    }
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Disconnected => write!(f, "the client has hung up"),
+            RpcError::MissingMsgId => write!(f, "RPC messages require a message id"),
+            RpcError::Remote(err) => write!(f, "the RPC failed with an error: {err}"),
+            RpcError::Full => write!(f, "the outbound queue is full"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
@@ -0,0 +1,396 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use rand::Rng;
+use serde_json::Value;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::{
+    ErrorBody, ErrorCode, KvBody, Message, MessageBody, MessageId, Node, NodeContext, Outbox,
+    TsoBody,
+};
+
+/// The environment variable that sets how stale `SeqKvService`'s reads can be, in milliseconds.
+pub const SEQ_KV_STALENESS_VAR: &str = "AURORA_SEQ_KV_STALENESS_MS";
+
+/// The environment variable that sets how long `LwwKvService` takes to merge writes, in
+/// milliseconds.
+pub const LWW_KV_MERGE_DELAY_VAR: &str = "AURORA_LWW_KV_MERGE_DELAY_MS";
+
+/// The staleness and merge delay that the services use when their variables are not set.
+const DEFAULT_DELAY: Duration = Duration::from_millis(500);
+
+/// A stand-in for Maelstrom's `lin-kv` service. Every request is applied to a single copy of the
+/// store as it arrives, so every operation is linearizable.
+///
+/// Like the other stand-ins, this is a regular node: it can be run in a `Cluster` (with the id
+/// `lin-kv`) or as the `lin-kv` binary.
+#[derive(Debug, Default)]
+pub struct LinKvService {
+    data: HashMap<String, Value>,
+}
+
+/// A stand-in for Maelstrom's `seq-kv` service. Writes and compare-and-sets are applied to the
+/// latest state of the store, but reads return a random state from up to the service's staleness
+/// ago. Reads never return a state older than the last one that the same client read or wrote, so
+/// the store is sequentially consistent.
+///
+/// When the service is run as a node, the staleness is read from `SEQ_KV_STALENESS_VAR` and is
+/// 500 milliseconds by default.
+#[derive(Debug)]
+pub struct SeqKvService {
+    /// How old the states that reads return can be
+    staleness: Duration,
+    /// The version of the latest state
+    version: u64,
+    /// When each version that can still be read became the latest, oldest first
+    created: VecDeque<(u64, Instant)>,
+    /// The values that each key has had in the versions that can still be read, oldest first
+    history: HashMap<String, Vec<(u64, Value)>>,
+    /// The newest version that each client has seen
+    floors: HashMap<String, u64>,
+}
+
+/// A stand-in for Maelstrom's `lww-kv` service. A client sees its own writes right away, but
+/// sees other clients' writes only after the service's merge delay. Of the writes that a client
+/// can see, the last one wins.
+///
+/// Compare-and-sets are checked against the client's view of the key, so they are not atomic
+/// across clients.
+///
+/// When the service is run as a node, the merge delay is read from `LWW_KV_MERGE_DELAY_VAR` and
+/// is 500 milliseconds by default.
+#[derive(Debug)]
+pub struct LwwKvService {
+    /// How long other clients' writes take to become visible
+    merge_delay: Duration,
+    /// The writes to each key, oldest first
+    writes: HashMap<String, Vec<LwwWrite>>,
+}
+
+/// A write to a key of a `LwwKvService`.
+#[derive(Debug)]
+struct LwwWrite {
+    writer: String,
+    value: Value,
+    at: Instant,
+}
+
+/// A stand-in for Maelstrom's `lin-tso` service. Timestamps start at zero and go up by one with
+/// each request.
+#[derive(Debug, Default)]
+pub struct LinTsoService {
+    next: u64,
+}
+
+impl Node for LinKvService {
+    type Body = KvBody;
+
+    fn init(_: &NodeContext<Self::Body>) -> Self {
+        Self::default()
+    }
+
+    fn handle_msg(
+        &mut self,
+        _: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> anyhow::Result<Outbox<Self::Body>> {
+        let body = match &msg.body {
+            KvBody::Read { key, .. } => read_ok(self.data.get(&key.to_string()))?,
+            KvBody::Write { key, value, .. } => {
+                self.data.insert(key.to_string(), value.clone());
+                write_ok()
+            }
+            KvBody::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+                ..
+            } => {
+                check_cas(self.data.get(&key.to_string()), from, *create_if_not_exists)?;
+                self.data.insert(key.to_string(), to.clone());
+                cas_ok()
+            }
+            KvBody::ReadOk { .. } | KvBody::WriteOk { .. } | KvBody::CasOk { .. } => {
+                return Ok(Outbox::new())
+            }
+        };
+        Ok(respond(msg, body))
+    }
+}
+
+impl SeqKvService {
+    /// Creates a store whose reads can be up to `staleness` old.
+    pub fn with_staleness(ctx: &NodeContext<KvBody>, staleness: Duration) -> Self {
+        Self {
+            staleness,
+            version: 0,
+            created: VecDeque::from([(0, ctx.now())]),
+            history: HashMap::new(),
+            floors: HashMap::new(),
+        }
+    }
+
+    /// Forgets the versions that are too old to be read and returns the oldest version that can
+    /// still be read.
+    fn oldest_readable(&mut self, now: Instant) -> u64 {
+        if let Some(start) = now.checked_sub(self.staleness) {
+            // A version can be read as long as it was the latest at some point in the window
+            while self.created.get(1).is_some_and(|(_, at)| *at <= start) {
+                self.created.pop_front();
+            }
+        }
+        self.created
+            .front()
+            .map(|(ver, _)| *ver)
+            .unwrap_or(self.version)
+    }
+
+    /// Returns the value that the key had in the given version.
+    fn value_at(&self, key: &str, version: u64) -> Option<&Value> {
+        self.history
+            .get(key)?
+            .iter()
+            .rev()
+            .find(|(ver, _)| *ver <= version)
+            .map(|(_, val)| val)
+    }
+
+    /// Writes a value to a key in a new version.
+    fn write(&mut self, src: &str, key: String, value: Value, now: Instant) {
+        self.version += 1;
+        self.created.push_back((self.version, now));
+        self.floors.insert(src.to_owned(), self.version);
+        let oldest = self.oldest_readable(now);
+        let history = self.history.entry(key).or_default();
+        history.push((self.version, value));
+        // Only the last value from before the oldest readable version is still needed
+        let keep = history
+            .iter()
+            .rposition(|(ver, _)| *ver <= oldest)
+            .unwrap_or(0);
+        history.drain(..keep);
+    }
+}
+
+impl Node for SeqKvService {
+    type Body = KvBody;
+
+    fn init(ctx: &NodeContext<Self::Body>) -> Self {
+        Self::with_staleness(ctx, delay_from_env(SEQ_KV_STALENESS_VAR))
+    }
+
+    fn handle_msg(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> anyhow::Result<Outbox<Self::Body>> {
+        let now = ctx.now();
+        let body = match &msg.body {
+            KvBody::Read { key, .. } => {
+                let floor = self.floors.get(&msg.src).copied().unwrap_or(0);
+                let oldest = self.oldest_readable(now).max(floor);
+                let version = ctx.rng().gen_range(oldest..=self.version);
+                self.floors.insert(msg.src.clone(), version);
+                read_ok(self.value_at(&key.to_string(), version))?
+            }
+            KvBody::Write { key, value, .. } => {
+                self.write(&msg.src, key.to_string(), value.clone(), now);
+                write_ok()
+            }
+            KvBody::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+                ..
+            } => {
+                let key = key.to_string();
+                check_cas(
+                    self.value_at(&key, self.version),
+                    from,
+                    *create_if_not_exists,
+                )?;
+                self.write(&msg.src, key, to.clone(), now);
+                cas_ok()
+            }
+            KvBody::ReadOk { .. } | KvBody::WriteOk { .. } | KvBody::CasOk { .. } => {
+                return Ok(Outbox::new())
+            }
+        };
+        Ok(respond(msg, body))
+    }
+}
+
+impl LwwKvService {
+    /// Creates a store that merges writes after `merge_delay`.
+    pub fn with_merge_delay(merge_delay: Duration) -> Self {
+        Self {
+            merge_delay,
+            writes: HashMap::new(),
+        }
+    }
+
+    /// Returns the value of a key as the given client sees it.
+    fn view(&self, src: &str, key: &str, now: Instant) -> Option<&Value> {
+        let delay = self.merge_delay;
+        self.writes
+            .get(key)?
+            .iter()
+            .rev()
+            .find(|write| write.writer == src || write.at + delay <= now)
+            .map(|write| &write.value)
+    }
+
+    /// Writes a value to a key for the given client.
+    fn write(&mut self, src: &str, key: String, value: Value, now: Instant) {
+        let delay = self.merge_delay;
+        let writes = self.writes.entry(key).or_default();
+        writes.push(LwwWrite {
+            writer: src.to_owned(),
+            value,
+            at: now,
+        });
+        // Writes before the last one that every client can see are never read again
+        let keep = writes
+            .iter()
+            .rposition(|write| write.at + delay <= now)
+            .unwrap_or(0);
+        writes.drain(..keep);
+    }
+}
+
+impl Node for LwwKvService {
+    type Body = KvBody;
+
+    fn init(_: &NodeContext<Self::Body>) -> Self {
+        Self::with_merge_delay(delay_from_env(LWW_KV_MERGE_DELAY_VAR))
+    }
+
+    fn handle_msg(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> anyhow::Result<Outbox<Self::Body>> {
+        let now = ctx.now();
+        let body = match &msg.body {
+            KvBody::Read { key, .. } => read_ok(self.view(&msg.src, &key.to_string(), now))?,
+            KvBody::Write { key, value, .. } => {
+                self.write(&msg.src, key.to_string(), value.clone(), now);
+                write_ok()
+            }
+            KvBody::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+                ..
+            } => {
+                let key = key.to_string();
+                check_cas(self.view(&msg.src, &key, now), from, *create_if_not_exists)?;
+                self.write(&msg.src, key, to.clone(), now);
+                cas_ok()
+            }
+            KvBody::ReadOk { .. } | KvBody::WriteOk { .. } | KvBody::CasOk { .. } => {
+                return Ok(Outbox::new())
+            }
+        };
+        Ok(respond(msg, body))
+    }
+}
+
+impl Node for LinTsoService {
+    type Body = TsoBody;
+
+    fn init(_: &NodeContext<Self::Body>) -> Self {
+        Self::default()
+    }
+
+    fn handle_msg(
+        &mut self,
+        _: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> anyhow::Result<Outbox<Self::Body>> {
+        let TsoBody::Ts { .. } = msg.body else {
+            return Ok(Outbox::new());
+        };
+        let ts = self.next;
+        self.next += 1;
+        let body = TsoBody::TsOk {
+            msg_id: None,
+            in_reply_to: MessageId::default(),
+            ts,
+        };
+        Ok(respond(msg, body))
+    }
+}
+
+/// Reads a delay in milliseconds from an environment variable. Delays that are not set or can not
+/// be parsed are the default.
+fn delay_from_env(var: &str) -> Duration {
+    let Ok(ms) = std::env::var(var) else {
+        return DEFAULT_DELAY;
+    };
+    match ms.parse() {
+        Ok(ms) => Duration::from_millis(ms),
+        Err(err) => {
+            warn!(var, value = %ms, error = %err, "ignoring an invalid delay");
+            DEFAULT_DELAY
+        }
+    }
+}
+
+/// Turns a request into a reply with the given body.
+fn respond<B: MessageBody>(mut msg: Message<B>, body: B) -> Outbox<B> {
+    // `into_response` and the client fill in the message ids
+    msg.into_response(|old| *old = body);
+    msg.into()
+}
+
+fn read_ok(value: Option<&Value>) -> Result<KvBody, ErrorBody> {
+    match value {
+        Some(value) => Ok(KvBody::ReadOk {
+            msg_id: None,
+            in_reply_to: MessageId::default(),
+            value: value.clone(),
+        }),
+        None => Err(ErrorBody::new(
+            ErrorCode::KeyDoesNotExist,
+            "the key does not exist",
+        )),
+    }
+}
+
+fn write_ok() -> KvBody {
+    KvBody::WriteOk {
+        msg_id: None,
+        in_reply_to: MessageId::default(),
+    }
+}
+
+fn cas_ok() -> KvBody {
+    KvBody::CasOk {
+        msg_id: None,
+        in_reply_to: MessageId::default(),
+    }
+}
+
+/// Checks whether a compare-and-set can be applied to a key with the given value.
+fn check_cas(current: Option<&Value>, from: &Value, create: bool) -> Result<(), ErrorBody> {
+    match current {
+        Some(current) if current == from => Ok(()),
+        Some(current) => Err(ErrorBody::new(
+            ErrorCode::PreconditionFailed,
+            format!("expected {from} but the value was {current}"),
+        )),
+        None if create => Ok(()),
+        None => Err(ErrorBody::new(
+            ErrorCode::KeyDoesNotExist,
+            "the key does not exist",
+        )),
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use tokio::task::{AbortHandle, JoinHandle};
+use tracing::Instrument;
+
+/// The reasons that the client can shut down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ShutdownReason {
+    /// The input was closed, so no more messages will arrive
+    InputClosed,
+    /// The process was asked to terminate
+    Terminated,
+}
+
+/// The background tasks that were spawned through a `Sender`. These are cancelled when the client
+/// shuts down.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TaskSet {
+    tasks: Arc<Mutex<Vec<AbortHandle>>>,
+}
+
+/// Listens for the signal that asks the process to terminate (SIGTERM on Unix and Ctrl-C
+/// elsewhere). A disabled listener never hears the signal.
+#[derive(Debug)]
+pub(crate) struct Terminate {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+    #[cfg(not(unix))]
+    enabled: bool,
+}
+
+impl TaskSet {
+    /// Spawns a task and tracks it.
+    pub(crate) fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        // The task stays in the span of whatever spawned it, which is usually the node's span
+        let handle = tokio::spawn(fut.in_current_span());
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(handle.abort_handle());
+        handle
+    }
+
+    /// Cancels all tracked tasks and returns how many were still running.
+    pub(crate) fn abort_all(&self) -> usize {
+        let mut tasks = self.tasks.lock().unwrap();
+        let running = tasks.drain(..).filter(|task| !task.is_finished());
+        running.map(|task| task.abort()).count()
+    }
+}
+
+impl Terminate {
+    /// Starts listening for the termination signal, unless the listener is disabled. A disabled
+    /// listener does not install a signal handler.
+    pub(crate) fn new(enabled: bool) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        let signal = match enabled {
+            true => Some(tokio::signal::unix::signal(
+                tokio::signal::unix::SignalKind::terminate(),
+            )?),
+            false => None,
+        };
+        Ok(Self {
+            #[cfg(unix)]
+            signal,
+            #[cfg(not(unix))]
+            enabled,
+        })
+    }
+
+    /// Waits for the termination signal. This is cancellation safe.
+    pub(crate) async fn recv(&mut self) {
+        #[cfg(unix)]
+        match &mut self.signal {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+        #[cfg(not(unix))]
+        match self.enabled {
+            true => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+            false => std::future::pending().await,
+        }
+    }
+}
+
+impl Display for ShutdownReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShutdownReason::InputClosed => write!(f, "the input was closed"),
+            ShutdownReason::Terminated => write!(f, "the process was terminated"),
+        }
+    }
+}
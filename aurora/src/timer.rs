@@ -0,0 +1,77 @@
+use std::{cmp::Reverse, collections::BinaryHeap, future::pending, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval_at, sleep_until, Instant, Interval, MissedTickBehavior};
+
+/// A new-type wrapper around the tokens used to identify timers. When a timer fires, its token is
+/// passed to the node's `on_timer` method.
+#[derive(
+    Serialize, Deserialize, Debug, Default, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(transparent)]
+pub struct TimerToken(pub u64);
+
+/// The things that timers can trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TimerEvent {
+    /// The node's tick interval has elapsed
+    Tick,
+    /// A one-shot timer has fired
+    Timer(TimerToken),
+}
+
+/// Tracks the node's periodic tick and all of its outstanding one-shot timers.
+#[derive(Debug, Default)]
+pub(crate) struct Timers {
+    tick: Option<Interval>,
+    // Timers are ordered by deadline and then by the order in which they were scheduled
+    timers: BinaryHeap<Reverse<(Instant, u64, TimerToken)>>,
+    counter: u64,
+}
+
+impl Timers {
+    /// Sets the interval at which the node's `on_tick` method is called. The first tick happens
+    /// one interval from now.
+    pub(crate) fn set_tick(&mut self, period: Option<Duration>) {
+        self.tick = period.map(|period| {
+            let mut tick = interval_at(Instant::now() + period, period);
+            tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            tick
+        });
+    }
+
+    /// Schedules a one-shot timer.
+    pub(crate) fn schedule(&mut self, at: Instant, token: TimerToken) {
+        self.counter += 1;
+        self.timers.push(Reverse((at, self.counter, token)));
+    }
+
+    /// Waits for the next tick or timer. If there are neither, this never resolves. This is
+    /// cancellation safe.
+    pub(crate) async fn next(&mut self) -> TimerEvent {
+        let deadline = self.timers.peek().map(|Reverse((at, _, _))| *at);
+        tokio::select! {
+            _ = next_tick(&mut self.tick) => TimerEvent::Tick,
+            _ = sleep_until_opt(deadline) => {
+                let Reverse((_, _, token)) = self.timers.pop().expect("a timer was scheduled");
+                TimerEvent::Timer(token)
+            }
+        }
+    }
+}
+
+async fn next_tick(tick: &mut Option<Interval>) {
+    match tick {
+        Some(tick) => {
+            tick.tick().await;
+        }
+        None => pending().await,
+    }
+}
+
+async fn sleep_until_opt(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => pending().await,
+    }
+}
@@ -0,0 +1,163 @@
+use std::{fmt::Display, sync::Arc};
+
+use tokio::sync::Mutex;
+
+use crate::{BodyTypes, Held, Holds, MessageBody, MessageId, NodeContext, RpcError};
+
+/// The name of Maelstrom's linearizable timestamp oracle.
+pub const LIN_TSO: &str = "lin-tso";
+
+/// The message body type used to talk to Maelstrom's timestamp oracle.
+#[derive(MessageBody, Debug, Clone, PartialEq, Eq)]
+pub enum TsoBody {
+    /// Asks for a timestamp
+    Ts {
+        /// The message id
+        msg_id: MessageId,
+    },
+    /// A timestamp that is larger than every one handed out before it
+    TsOk {
+        /// The message id, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        msg_id: Option<MessageId>,
+        /// The id of the message that this is responding to
+        in_reply_to: MessageId,
+        /// The timestamp
+        ts: u64,
+    },
+}
+
+/// A client for `lin-tso`, which hands out timestamps that increase in the order that they were
+/// asked for across the whole cluster. Clients are cheap to clone, and clones share their batch.
+///
+/// Like `KvClient`, the client works with any body type that holds a `TsoBody`.
+///
+/// By default, every timestamp is a request to the oracle. Clients made with `TsoClient::batched`
+/// instead reserve a range of timestamps with each request and hand them out locally: the oracle's
+/// timestamp `ts` reserves `ts * size` up to (but not including) `(ts + 1) * size`. Batched
+/// timestamps are still unique and increase on each node, but they are only ordered against other
+/// nodes' timestamps by when their range was reserved. Every node that shares timestamps needs to
+/// use the same batch size.
+#[derive(Debug, Clone)]
+pub struct TsoClient<B: MessageBody> {
+    ctx: NodeContext<B>,
+    held: Held<B, TsoBody>,
+    batch: Option<Arc<Mutex<Batch>>>,
+}
+
+/// The range of timestamps that a batched client has reserved.
+#[derive(Debug)]
+struct Batch {
+    size: u64,
+    next: u64,
+    end: u64,
+}
+
+/// The errors that can occur while using a `TsoClient`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TsoError {
+    /// The request could not be sent or the oracle replied with an error
+    Rpc(RpcError),
+    /// The oracle replied with the wrong type of body
+    Invalid(String),
+}
+
+impl<B: MessageBody> TsoClient<B> {
+    /// Creates a client that asks the oracle for every timestamp.
+    pub fn new<I>(ctx: &NodeContext<B>) -> Self
+    where
+        B: Holds<TsoBody, I>,
+    {
+        Self {
+            ctx: ctx.clone(),
+            held: Held::new(),
+            batch: None,
+        }
+    }
+
+    /// Creates a client that reserves `size` timestamps with each request to the oracle. A size
+    /// of zero is treated as one.
+    pub fn batched<I>(ctx: &NodeContext<B>, size: u64) -> Self
+    where
+        B: Holds<TsoBody, I>,
+    {
+        Self {
+            ctx: ctx.clone(),
+            held: Held::new(),
+            batch: Some(Arc::new(Mutex::new(Batch {
+                size: size.max(1),
+                next: 0,
+                end: 0,
+            }))),
+        }
+    }
+
+    /// Returns a timestamp that is larger than every other timestamp that this client has handed
+    /// out. Batched clients return `TsoError::Invalid` if the oracle's timestamp reserves a range
+    /// that does not fit in a `u64`.
+    pub async fn ts(&self) -> Result<u64, TsoError> {
+        let Some(batch) = &self.batch else {
+            return self.request().await;
+        };
+        // The lock is held while a new range is reserved so that concurrent callers wait for it
+        // instead of each reserving their own
+        let mut batch = batch.lock().await;
+        if batch.next == batch.end {
+            let ts = self.request().await?;
+            // A range that does not fit in a `u64` would hand out the same timestamp forever
+            let (next, end) = ts
+                .checked_mul(batch.size)
+                .and_then(|next| Some((next, next.checked_add(batch.size)?)))
+                .ok_or_else(|| {
+                    TsoError::Invalid(format!(
+                        "the timestamp {ts} is too large for batches of {}",
+                        batch.size
+                    ))
+                })?;
+            batch.next = next;
+            batch.end = end;
+        }
+        let ts = batch.next;
+        batch.next += 1;
+        Ok(ts)
+    }
+
+    /// Asks the oracle for a timestamp.
+    async fn request(&self) -> Result<u64, TsoError> {
+        let body = (self.held.wrap)(TsoBody::Ts {
+            msg_id: MessageId::default(),
+        });
+        let reply = self
+            .ctx
+            .sender()
+            .rpc_with(self.ctx.message(LIN_TSO, body), self.held.parse)
+            .await?;
+        match (self.held.unwrap)(reply.body) {
+            Some(TsoBody::TsOk { ts, .. }) => Ok(ts),
+            Some(body) => Err(TsoError::Invalid(format!(
+                "expected a `ts_ok` reply but got `{}`",
+                body.body_type()
+            ))),
+            None => Err(TsoError::Invalid(String::from(
+                "the reply was not a timestamp body",
+            ))),
+        }
+    }
+}
+
+impl From<RpcError> for TsoError {
+    fn from(err: RpcError) -> Self {
+        TsoError::Rpc(err)
+    }
+}
+
+impl Display for TsoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TsoError::Rpc(err) => write!(f, "the request failed: {err}"),
+            TsoError::Invalid(err) => write!(f, "invalid timestamp data: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for TsoError {}
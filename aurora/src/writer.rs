@@ -0,0 +1,148 @@
+use std::{
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc::{self, Receiver, Sender},
+    task::JoinHandle,
+};
+
+use tracing::{Instrument, Span};
+
+use crate::{log_message, Error};
+
+/// The most messages that the writer will write before flushing the output.
+const MAX_BATCH: usize = 256;
+
+/// The most messages that can wait for the writer task. Once this many are waiting, the client
+/// waits for the writer before it sends anything else, so a slow output holds messages back in the
+/// outbound queue, where `ClientConfig::outbound_capacity` applies.
+const CAPACITY: usize = 4 * MAX_BATCH;
+
+/// A snapshot of the number of messages that the client has written to its output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OutputStats {
+    /// The number of messages that have been written
+    pub messages: u64,
+    /// The number of bytes that have been written, including newlines
+    pub bytes: u64,
+    /// The number of times that the output has been flushed. Each flush writes a batch of
+    /// messages.
+    pub flushes: u64,
+}
+
+/// The handle to the task that writes messages to the output.
+#[derive(Debug)]
+pub(crate) struct Writer {
+    send: Option<Sender<Vec<u8>>>,
+    handle: Option<JoinHandle<std::io::Result<()>>>,
+    stats: Arc<Counters>,
+}
+
+/// The counters behind `OutputStats`. These are updated by the writer task.
+#[derive(Debug, Default)]
+struct Counters {
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    flushes: AtomicU64,
+}
+
+impl Writer {
+    /// Spawns the writer task.
+    pub(crate) fn new<W>(output: W, span: Span) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (send, recv) = mpsc::channel(CAPACITY);
+        let stats = Arc::new(Counters::default());
+        let handle = tokio::spawn(write_loop(output, recv, stats.clone()).instrument(span));
+        Self {
+            send: Some(send),
+            handle: Some(handle),
+            stats,
+        }
+    }
+
+    /// Serializes a message and passes it to the writer task, waiting if the task has too many
+    /// messages to write already. Messages are written in the order that they are passed in.
+    /// Messages are serialized here rather than in the task so that a message that can not be
+    /// serialized is an error for the caller. If the writer task has stopped, the error that
+    /// stopped it is returned.
+    pub(crate) async fn write<T: Serialize>(&mut self, msg: T) -> Result<(), Error> {
+        let json = serde_json::to_vec(&msg).map_err(Error::Serialize)?;
+        let sent = match &self.send {
+            Some(send) => send.send(json).await.is_ok(),
+            None => false,
+        };
+        if sent {
+            return Ok(());
+        }
+        self.close().await?;
+        Err(std::io::Error::from(ErrorKind::BrokenPipe).into())
+    }
+
+    /// Waits for every message to be written and then stops the writer task.
+    pub(crate) async fn close(&mut self) -> Result<(), Error> {
+        self.send = None;
+        match self.handle.take() {
+            Some(handle) => match handle.await {
+                Ok(res) => Ok(res?),
+                Err(err) => Err(std::io::Error::other(err).into()),
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Returns how much the writer has written so far.
+    pub(crate) fn stats(&self) -> OutputStats {
+        OutputStats {
+            messages: self.stats.messages.load(Ordering::Relaxed),
+            bytes: self.stats.bytes.load(Ordering::Relaxed),
+            flushes: self.stats.flushes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Writes messages until every sender has been dropped. All messages that are waiting in the
+/// channel are written as a single batch, which is then flushed.
+async fn write_loop<W>(
+    mut output: W,
+    mut recv: Receiver<Vec<u8>>,
+    stats: Arc<Counters>,
+) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = Vec::new();
+    while let Some(json) = recv.recv().await {
+        append(&mut buf, &json);
+        let mut count = 1;
+        while count < MAX_BATCH {
+            let Ok(json) = recv.try_recv() else {
+                break;
+            };
+            append(&mut buf, &json);
+            count += 1;
+        }
+        output.write_all(&buf).await?;
+        output.flush().await?;
+        stats.messages.fetch_add(count as u64, Ordering::Relaxed);
+        stats.bytes.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        stats.flushes.fetch_add(1, Ordering::Relaxed);
+        buf.clear();
+    }
+    Ok(())
+}
+
+/// Adds a serialized message to the end of the buffer as a line.
+fn append(buf: &mut Vec<u8>, json: &[u8]) {
+    log_message(json, "sent message");
+    buf.extend_from_slice(json);
+    buf.push(b'\n');
+}
@@ -2,8 +2,12 @@ pub mod utils;
 
 #[cfg(test)]
 mod tests {
-    use aurora::{BroadcastBody, EchoBody, IdBody, InitBody};
+    use aurora::{
+        BodyTypes, BroadcastBody, EchoBody, ErrorBody, ErrorCode, GCounterBody, IdBody, Inbound,
+        InitBody, MessageBody, MessageId, OrError,
+    };
     use serde::de::DeserializeOwned;
+    use serde_json::json;
 
     use super::utils::*;
 
@@ -49,6 +53,7 @@ mod tests {
         assert_eq!(json, KNOWN_ID_BODY);
         let data: IdBody = serde_json::from_str(&json).unwrap();
         assert_eq!(data, req);
+        assert_eq!(data.msg_id(), Some(MessageId(1)));
 
         /* ------ Response ------ */
         let resp = known_id_ok_body();
@@ -56,6 +61,7 @@ mod tests {
         assert_eq!(json, KNOWN_ID_OK_BODY);
         let data: IdBody = serde_json::from_str(&json).unwrap();
         assert_eq!(data, resp);
+        assert_eq!(data.in_reply_to(), Some(MessageId(1)));
     }
 
     #[test]
@@ -88,6 +94,7 @@ mod tests {
         let resp = known_read_ok_body();
         let json = serde_json::to_string(&resp).unwrap();
         assert_eq!(json[..30], KNOWN_READ_OK_BODY[..30]);
+        assert!(unordered_json_eq(&json, KNOWN_READ_OK_BODY));
         let data: BroadcastBody = serde_json::from_str(&json).unwrap();
         assert_eq!(data, resp);
     }
@@ -98,7 +105,7 @@ mod tests {
         let req = known_topology_body();
         let json = serde_json::to_string(&req).unwrap();
         assert_eq!(json[..30], KNOWN_TOPOLOGY_BODY[..30]);
-        assert_eq!(json[69..], KNOWN_TOPOLOGY_BODY[69..]);
+        assert!(unordered_json_eq(&json, KNOWN_TOPOLOGY_BODY));
         let data: BroadcastBody = serde_json::from_str(&json).unwrap();
         assert_eq!(data, req);
 
@@ -110,6 +117,54 @@ mod tests {
         assert_eq!(data, resp);
     }
 
+    #[test]
+    fn g_counter_tests() {
+        /* ------ Request ------ */
+        let req = known_add_body();
+        let json = serde_json::to_string(&req).unwrap();
+        assert_eq!(json, KNOWN_ADD_BODY);
+        let data: GCounterBody = serde_json::from_str(&json).unwrap();
+        assert_eq!(data, req);
+
+        /* ------ Response ------ */
+        let resp = known_add_ok_body();
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, KNOWN_ADD_OK_BODY);
+        let data: GCounterBody = serde_json::from_str(&json).unwrap();
+        assert_eq!(data, resp);
+
+        let resp = known_counter_read_ok_body();
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, KNOWN_COUNTER_READ_OK_BODY);
+        let data: GCounterBody = serde_json::from_str(&json).unwrap();
+        assert_eq!(data, resp);
+    }
+
+    #[test]
+    fn error_tests() {
+        let resp = known_error_body();
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(json, KNOWN_ERROR_BODY);
+        let data: ErrorBody = serde_json::from_str(&json).unwrap();
+        assert_eq!(data, resp);
+        assert!(serde_json::from_str::<ErrorBody>(KNOWN_ECHO_OK_BODY).is_err());
+    }
+
+    #[test]
+    fn error_code_tests() {
+        for code in [0, 1, 10, 11, 12, 13, 14, 20, 21, 22, 30, 1000] {
+            let json = code.to_string();
+            let data: ErrorCode = serde_json::from_str(&json).unwrap();
+            assert_eq!(data.code(), code);
+            assert_eq!(serde_json::to_string(&data).unwrap(), json);
+        }
+        assert!(ErrorCode::NotSupported.is_definite());
+        assert!(ErrorCode::PreconditionFailed.is_definite());
+        assert!(!ErrorCode::Timeout.is_definite());
+        assert!(!ErrorCode::Crash.is_definite());
+        assert!(!ErrorCode::Custom(1000).is_definite());
+    }
+
     #[test]
     fn no_mixed_signals() {
         fn valid_deserialization<T: DeserializeOwned + PartialEq>(s: &str, known: T) -> bool {
@@ -148,4 +203,106 @@ mod tests {
             assert_eq!(count, 1);
         }
     }
+
+    #[derive(MessageBody, Debug, Clone, PartialEq, Eq)]
+    enum DerivedBody {
+        Read {
+            msg_id: MessageId,
+            key: u64,
+        },
+        ReadOk {
+            msg_id: Option<MessageId>,
+            in_reply_to: MessageId,
+            value: u64,
+        },
+        #[serde(rename = "cas")]
+        #[body(response = Ack)]
+        CompareAndSwap {
+            msg_id: MessageId,
+            key: u64,
+            #[serde(default)]
+            create_if_not_exists: bool,
+        },
+        Ack {
+            in_reply_to: Option<MessageId>,
+        },
+        Ping,
+    }
+
+    #[test]
+    fn derived_bodies_are_snake_case() {
+        let body = DerivedBody::ReadOk {
+            msg_id: None,
+            in_reply_to: MessageId(3),
+            value: 5,
+        };
+        let json = serde_json::to_value(&body).unwrap();
+        assert_eq!(
+            json,
+            json!({"type": "read_ok", "msg_id": null, "in_reply_to": 3, "value": 5})
+        );
+        assert_eq!(serde_json::from_value::<DerivedBody>(json).unwrap(), body);
+        let body: DerivedBody =
+            serde_json::from_str(r#"{"type":"cas","msg_id":1,"key":2}"#).unwrap();
+        assert_eq!(
+            body,
+            DerivedBody::CompareAndSwap {
+                msg_id: MessageId(1),
+                key: 2,
+                create_if_not_exists: false,
+            }
+        );
+        let json = serde_json::to_string(&DerivedBody::Ping).unwrap();
+        assert_eq!(json, r#"{"type":"ping"}"#);
+        // Unknown types are still reported as unknown variants
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"pong"}}"#;
+        assert_eq!(
+            Inbound::<DerivedBody>::from_line(line).unwrap(),
+            Inbound::Main(known_request(OrError::Unknown(json!({"type": "pong"}))))
+        );
+    }
+
+    #[test]
+    fn derived_bodies_have_ids() {
+        let mut body = DerivedBody::ReadOk {
+            msg_id: None,
+            in_reply_to: MessageId(3),
+            value: 5,
+        };
+        assert_eq!(body.msg_id(), None);
+        body.update_msg_id(MessageId(8));
+        body.update_in_reply_to(MessageId(4));
+        assert_eq!(body.msg_id(), Some(MessageId(8)));
+        assert_eq!(body.in_reply_to(), Some(MessageId(4)));
+        let mut body = DerivedBody::Ack { in_reply_to: None };
+        body.update_msg_id(MessageId(8));
+        body.update_in_reply_to(MessageId(4));
+        assert_eq!(body.msg_id(), None);
+        assert_eq!(body.in_reply_to(), Some(MessageId(4)));
+        let mut body = DerivedBody::Ping;
+        body.update_msg_id(MessageId(8));
+        assert_eq!(body, DerivedBody::Ping);
+    }
+
+    #[test]
+    fn derived_bodies_pair_requests_and_responses() {
+        assert_eq!(
+            DerivedBody::TYPES,
+            ["read", "read_ok", "cas", "ack", "ping"]
+        );
+        assert_eq!(DerivedBody::PAIRS, [("read", "read_ok"), ("cas", "ack")]);
+        let body = DerivedBody::Read {
+            msg_id: MessageId(1),
+            key: 2,
+        };
+        assert_eq!(body.body_type(), "read");
+        assert_eq!(body.response_type(), Some("read_ok"));
+        assert!(!body.is_response());
+        let body = DerivedBody::Ack { in_reply_to: None };
+        assert_eq!(body.response_type(), None);
+        assert!(body.is_response());
+        assert_eq!(DerivedBody::Ping.response_type(), None);
+        assert!(!DerivedBody::Ping.is_response());
+        assert_eq!(known_echo_body().response_type(), Some("echo_ok"));
+    }
 }
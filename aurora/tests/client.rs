@@ -1,32 +1,880 @@
+pub mod utils;
+
 #[cfg(test)]
 mod tests {
-    use aurora::{Client, Message, MessageBody, MessageId, Node};
+    use aurora::{
+        AsyncNode, Client, ClientConfig, ConcurrentNode, DedupConfig, EchoBody, Error, ErrorBody,
+        ErrorCode, Message, MessageBody, MessageId, Node, NodeContext, Outbox, OverflowPolicy,
+        TimerToken,
+    };
     use serde::{Deserialize, Serialize};
-    use tokio::sync::mpsc::UnboundedSender;
+    use serde_json::{json, Value};
+    use std::{collections::HashMap, time::Duration};
+    use tokio::io::{split, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use super::utils::*;
 
     #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
     struct DummyBody;
 
     impl MessageBody for DummyBody {}
 
+    /// JSON object keys have to be strings, so this body can never be serialized.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    struct UnserializableBody(HashMap<(u8, u8), u8>);
+
+    impl MessageBody for UnserializableBody {}
+
     struct DummyNode;
 
     impl Node for DummyNode {
         type Body = DummyBody;
 
-        fn init(_: UnboundedSender<Message<Self::Body>>, _: String, _: Vec<String>) -> Self {
+        fn init(_: &NodeContext<Self::Body>) -> Self {
             Self
         }
 
-        fn next_id(&mut self) -> MessageId {
-            MessageId(0)
+        fn handle_msg(
+            &mut self,
+            _: &NodeContext<Self::Body>,
+            _: Message<Self::Body>,
+        ) -> anyhow::Result<Outbox<Self::Body>> {
+            Ok(Outbox::new())
+        }
+    }
+
+    /// Echos messages back, fails on "fail", asks `n2` for the echo on "ask" (and also sends five
+    /// notes on "ask and flood"), sends an unrelated `echo_ok` to `c1` on "forward", and sets a
+    /// timer on "timer". Stray `echo_ok`s are noted to `c1`. Nodes named "ticker" tick.
+    struct TestNode {
+        ticks: bool,
+    }
+
+    impl Node for TestNode {
+        type Body = EchoBody;
+
+        fn init(ctx: &NodeContext<Self::Body>) -> Self {
+            Self {
+                ticks: ctx.node_id() == "ticker",
+            }
         }
 
         fn handle_msg(
             &mut self,
-            _: Message<Self::Body>,
-        ) -> anyhow::Result<Option<Message<Self::Body>>> {
-            Ok(None)
+            ctx: &NodeContext<Self::Body>,
+            mut msg: Message<Self::Body>,
+        ) -> anyhow::Result<Outbox<Self::Body>> {
+            let (msg_id, echo) = match msg.body.clone() {
+                EchoBody::Echo { msg_id, echo } => (msg_id, echo),
+                EchoBody::EchoOk { echo, .. } => return Ok(note(ctx, format!("got {echo}")).into()),
+            };
+            match echo.as_str() {
+                "fail" => Err(ErrorBody::new(ErrorCode::NotSupported, "can't echo that").into()),
+                "ask" | "ask and flood" => {
+                    let question = ctx.message(
+                        "n2",
+                        EchoBody::Echo {
+                            msg_id: MessageId(100),
+                            echo: String::from("question"),
+                        },
+                    );
+                    let resp = ctx.sender().rpc(question);
+                    if echo == "ask and flood" {
+                        for i in 0..5 {
+                            let _ = ctx.sender().send(note(ctx, format!("flood {i}")));
+                        }
+                    }
+                    let sender = ctx.sender().clone();
+                    let reply_id = ctx.next_id();
+                    tokio::spawn(async move {
+                        let EchoBody::EchoOk { echo, .. } = resp.await.unwrap().body else {
+                            panic!("expected an echo_ok reply")
+                        };
+                        msg.into_response(|body| {
+                            *body = EchoBody::EchoOk {
+                                echo,
+                                msg_id: reply_id,
+                                in_reply_to: msg_id,
+                            }
+                        });
+                        sender.send(msg).unwrap();
+                    });
+                    Ok(Outbox::new())
+                }
+                "flood" => {
+                    let queued = (0..5)
+                        .filter(|i| ctx.sender().send(note(ctx, format!("flood {i}"))).is_ok())
+                        .count();
+                    let depth = ctx.sender().queue_stats().depth;
+                    msg.into_response(|body| {
+                        *body = EchoBody::EchoOk {
+                            echo: format!("{queued} queued, {depth} waiting"),
+                            msg_id: MessageId::default(),
+                            in_reply_to: msg_id,
+                        }
+                    });
+                    Ok(msg.into())
+                }
+                "fanout" => {
+                    let note = note(ctx, String::from("fanned out"));
+                    let reply_id = ctx.next_id();
+                    msg.into_response(|body| {
+                        *body = EchoBody::EchoOk {
+                            echo,
+                            msg_id: reply_id,
+                            in_reply_to: msg_id,
+                        }
+                    });
+                    Ok(Outbox::from(vec![msg, note]))
+                }
+                "forward" => {
+                    let body = EchoBody::EchoOk {
+                        echo,
+                        msg_id: MessageId::default(),
+                        in_reply_to: MessageId::default(),
+                    };
+                    Ok(ctx.message("c1", body).into())
+                }
+                "timer" => {
+                    ctx.schedule(Duration::from_millis(10), TimerToken(5))?;
+                    Ok(Outbox::new())
+                }
+                _ => {
+                    let reply_id = ctx.next_id();
+                    msg.into_response(|body| {
+                        *body = EchoBody::EchoOk {
+                            echo,
+                            msg_id: reply_id,
+                            in_reply_to: msg_id,
+                        }
+                    });
+                    Ok(msg.into())
+                }
+            }
         }
+
+        /// Answers "ping" messages, which `EchoBody` does not know, with a raw "pong".
+        fn handle_unknown(
+            &mut self,
+            _: &NodeContext<Self::Body>,
+            mut msg: Message<Value>,
+        ) -> anyhow::Result<Outbox<Value>> {
+            if msg.body["type"] != "ping" {
+                return Err(ErrorBody::unknown_type(&msg.body).into());
+            }
+            // `into_response` and the client fill in the ids
+            msg.into_response(|body| *body = json!({"type": "pong", "in_reply_to": null}));
+            Ok(msg.into())
+        }
+
+        fn tick_interval(&self) -> Option<Duration> {
+            self.ticks.then_some(Duration::from_millis(10))
+        }
+
+        fn on_tick(&mut self, ctx: &NodeContext<Self::Body>) -> anyhow::Result<Outbox<Self::Body>> {
+            Ok(note(ctx, String::from("tick")).into())
+        }
+
+        fn on_timer(
+            &mut self,
+            ctx: &NodeContext<Self::Body>,
+            token: TimerToken,
+        ) -> anyhow::Result<Outbox<Self::Body>> {
+            Ok(note(ctx, format!("timer {}", token.0)).into())
+        }
+
+        fn shutdown(&mut self, ctx: &NodeContext<Self::Body>) -> anyhow::Result<()> {
+            ctx.sender().send(note(ctx, String::from("shutdown")))?;
+            Ok(())
+        }
+    }
+
+    fn note(ctx: &NodeContext<EchoBody>, echo: String) -> Message<EchoBody> {
+        let msg_id = ctx.next_id();
+        ctx.message("c1", EchoBody::Echo { msg_id, echo })
+    }
+
+    /// Echos messages back, but asks `n2` for the echo (and awaits the answer) on "ask" and waits
+    /// to send five notes on "flood".
+    struct AsyncTestNode;
+
+    impl AsyncNode for AsyncTestNode {
+        type Body = EchoBody;
+
+        async fn init(_: &NodeContext<Self::Body>) -> Self {
+            Self
+        }
+
+        async fn handle_msg(
+            &mut self,
+            ctx: &NodeContext<Self::Body>,
+            msg: Message<Self::Body>,
+        ) -> anyhow::Result<Outbox<Self::Body>> {
+            echo_or_ask(ctx, msg).await
+        }
+    }
+
+    /// The same as `AsyncTestNode`, but its handlers can run concurrently.
+    struct ConcurrentTestNode;
+
+    impl ConcurrentNode for ConcurrentTestNode {
+        type Body = EchoBody;
+
+        async fn init(_: &NodeContext<Self::Body>) -> Self {
+            Self
+        }
+
+        async fn handle_msg(
+            &self,
+            ctx: &NodeContext<Self::Body>,
+            msg: Message<Self::Body>,
+        ) -> anyhow::Result<Outbox<Self::Body>> {
+            echo_or_ask(ctx, msg).await
+        }
+    }
+
+    async fn echo_or_ask(
+        ctx: &NodeContext<EchoBody>,
+        mut msg: Message<EchoBody>,
+    ) -> anyhow::Result<Outbox<EchoBody>> {
+        let EchoBody::Echo { mut echo, .. } = msg.body.clone() else {
+            return Ok(Outbox::new());
+        };
+        if echo == "ask" {
+            let question = ctx.message(
+                "n2",
+                EchoBody::Echo {
+                    msg_id: MessageId(100),
+                    echo: String::from("question"),
+                },
+            );
+            let EchoBody::EchoOk { echo: answer, .. } = ctx.sender().rpc(question).await?.body
+            else {
+                anyhow::bail!("expected an echo_ok reply")
+            };
+            echo = answer;
+        }
+        if echo == "flood" {
+            for i in 0..5 {
+                ctx.sender()
+                    .send_wait(note(ctx, format!("flood {i}")))
+                    .await?;
+            }
+        }
+        msg.into_response(|body| {
+            *body = EchoBody::EchoOk {
+                echo,
+                msg_id: MessageId(101),
+                // `into_response` fills this in
+                in_reply_to: MessageId::default(),
+            }
+        });
+        Ok(msg.into())
+    }
+
+    /// The body that `n2` answers the node's questions with.
+    fn answer() -> Value {
+        json!({"type": "echo_ok", "echo": "answer", "msg_id": 3})
+    }
+
+    #[test]
+    fn default_body_has_no_reply_info() {
+        let msg = Message::new("c1", "n1", DummyBody);
+        assert_eq!(msg.body.msg_id(), None);
+        assert_eq!(msg.body.in_reply_to(), None);
+        let ctx = NodeContext::detached("n1", vec![String::from("n1")]);
+        assert!(Node::handle_msg(&mut DummyNode, &ctx, msg)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn context_knows_the_cluster() {
+        let nodes = vec![String::from("n1"), String::from("n2"), String::from("n3")];
+        let ctx = NodeContext::<EchoBody>::detached("n2", nodes);
+        assert_eq!(ctx.node_id(), "n2");
+        assert_eq!(ctx.peers().collect::<Vec<_>>(), ["n1", "n3"]);
+        let msg = ctx.message(
+            "n3",
+            EchoBody::Echo {
+                msg_id: ctx.next_id(),
+                echo: String::from("hi"),
+            },
+        );
+        assert_eq!(msg.src, "n2");
+        assert_eq!(msg.dest, "n3");
+        assert_eq!(ctx.next_id(), MessageId(1));
+        assert!(ctx.sender().send(msg).is_err());
+    }
+
+    #[tokio::test]
+    async fn messages_are_handled() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness.send_line(KNOWN_ECHO_MSG).await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["dest"], "c1");
+        assert_eq!(resp["body"]["type"], "echo_ok");
+        assert_eq!(resp["body"]["echo"], "Please echo 35");
+        assert_eq!(resp["body"]["in_reply_to"], 1);
+    }
+
+    #[tokio::test]
+    async fn input_closed() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness.input.shutdown().await.unwrap();
+        let note = harness.recv().await;
+        assert_eq!(note["body"]["echo"], "shutdown");
+        let res = harness.handle.await.unwrap();
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn input_closed_while_awaiting_rpcs() {
+        let mut harness = Harness::init::<AsyncTestNode>().await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"ask"}}"#)
+            .await;
+        assert_eq!(harness.recv().await["dest"], "n2");
+        harness.input.shutdown().await.unwrap();
+        // The answer can never arrive, so the handler fails rather than waiting forever
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["type"], "error");
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        let res = tokio::time::timeout(Duration::from_secs(1), harness.handle).await;
+        assert!(res.unwrap().unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn input_closed_while_awaiting_concurrent_rpcs() {
+        let mut harness =
+            Harness::init_concurrent::<ConcurrentTestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"ask"}}"#)
+            .await;
+        assert_eq!(harness.recv().await["dest"], "n2");
+        harness.input.shutdown().await.unwrap();
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["type"], "error");
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        let res = tokio::time::timeout(Duration::from_secs(1), harness.handle).await;
+        assert!(res.unwrap().unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn handler_errors_are_replied_to() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"fail"}}"#,
+            )
+            .await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["dest"], "c1");
+        assert_eq!(resp["body"]["type"], "error");
+        assert_eq!(resp["body"]["code"], 10);
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+    }
+
+    #[tokio::test]
+    async fn unknown_types_are_not_supported() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"frobnicate","msg_id":7}}"#)
+            .await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["type"], "error");
+        assert_eq!(resp["body"]["code"], 10);
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        assert_eq!(resp["body"]["text"], "unknown body type `frobnicate`");
+        // The node keeps going
+        harness.send_line(KNOWN_ECHO_MSG).await;
+        assert_eq!(harness.recv().await["body"]["type"], "echo_ok");
+    }
+
+    #[tokio::test]
+    async fn unknown_types_can_be_handled() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":7},"id":3}"#)
+            .await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["dest"], "c1");
+        assert_eq!(resp["body"]["type"], "pong");
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        assert!(resp["body"]["msg_id"].is_u64());
+        assert!(resp.get("id").is_none());
+    }
+
+    /// Asks the node to flood its queue and returns the echoes of the next `count` messages.
+    async fn flood(harness: &mut Harness, count: usize) -> Vec<String> {
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"flood"}}"#,
+            )
+            .await;
+        let mut echoes = Vec::new();
+        for _ in 0..count {
+            let msg = harness.recv().await;
+            echoes.push(msg["body"]["echo"].as_str().unwrap().to_owned());
+        }
+        echoes
+    }
+
+    #[tokio::test]
+    async fn full_queues_can_drop_the_oldest() {
+        let config = ClientConfig {
+            outbound_capacity: Some(2),
+            overflow: OverflowPolicy::DropOldest,
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        let echoes = flood(&mut harness, 3).await;
+        assert_eq!(echoes, ["5 queued, 2 waiting", "flood 3", "flood 4"]);
+    }
+
+    #[tokio::test]
+    async fn dropping_the_oldest_keeps_rpcs() {
+        let config = ClientConfig {
+            outbound_capacity: Some(2),
+            overflow: OverflowPolicy::DropOldest,
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"ask and flood"}}"#,
+            )
+            .await;
+        // The notes push each other out, but the question stays queued
+        let question = harness.recv().await;
+        assert_eq!(question["dest"], "n2");
+        assert_eq!(harness.recv().await["body"]["echo"], "flood 4");
+        harness.respond(&question, answer()).await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["echo"], "answer");
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+    }
+
+    #[tokio::test]
+    async fn full_queues_can_reject() {
+        let config = ClientConfig {
+            outbound_capacity: Some(2),
+            overflow: OverflowPolicy::Error,
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        let echoes = flood(&mut harness, 3).await;
+        assert_eq!(echoes, ["2 queued, 2 waiting", "flood 0", "flood 1"]);
+    }
+
+    #[tokio::test]
+    async fn full_queues_can_wait() {
+        let config = ClientConfig {
+            outbound_capacity: Some(1),
+            overflow: OverflowPolicy::Await,
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<AsyncTestNode>(config).await;
+        let mut echoes = flood(&mut harness, 6).await;
+        // The reply is not queued, so it can overtake the last of the notes
+        let reply = echoes.iter().position(|echo| echo == "flood").unwrap();
+        echoes.remove(reply);
+        assert_eq!(
+            echoes,
+            ["flood 0", "flood 1", "flood 2", "flood 3", "flood 4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_waiting_sends_await() {
+        // Sends that can not wait fail like they do under `OverflowPolicy::Error`
+        let config = ClientConfig {
+            outbound_capacity: Some(2),
+            overflow: OverflowPolicy::Await,
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        let echoes = flood(&mut harness, 3).await;
+        assert_eq!(echoes, ["2 queued, 2 waiting", "flood 0", "flood 1"]);
+    }
+
+    #[tokio::test]
+    async fn waiting_sends_follow_other_policies() {
+        let config = ClientConfig {
+            outbound_capacity: Some(1),
+            overflow: OverflowPolicy::DropOldest,
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<AsyncTestNode>(config).await;
+        // The notes push each other out instead of waiting for room
+        let mut echoes = flood(&mut harness, 2).await;
+        echoes.sort();
+        assert_eq!(echoes, ["flood", "flood 4"]);
+    }
+
+    #[tokio::test]
+    async fn rpc_replies_are_routed() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"ask"}}"#)
+            .await;
+        let question = harness.recv().await;
+        assert_eq!(question["dest"], "n2");
+        harness.respond(&question, answer()).await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["dest"], "c1");
+        assert_eq!(resp["body"]["echo"], "answer");
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+    }
+
+    #[tokio::test]
+    async fn async_handlers_can_await_rpcs() {
+        let mut harness = Harness::init_with::<AsyncTestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"ask"}}"#)
+            .await;
+        let question = harness.recv().await;
+        assert_eq!(question["dest"], "n2");
+        // This message has to wait for the first handler to finish
+        harness.send_line(KNOWN_ECHO_MSG).await;
+        harness.respond(&question, answer()).await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["echo"], "answer");
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["echo"], "Please echo 35");
+        assert_eq!(resp["body"]["in_reply_to"], 1);
+    }
+
+    #[tokio::test]
+    async fn timers_are_delivered() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"timer"}}"#,
+            )
+            .await;
+        let note = harness.recv().await;
+        assert_eq!(note["body"]["type"], "echo");
+        assert_eq!(note["body"]["echo"], "timer 5");
+    }
+
+    #[tokio::test]
+    async fn ticks_are_delivered() {
+        let mut harness = Harness::new::<TestNode>(ClientConfig::default());
+        harness
+            .send_line(r#"{"src":"c1","dest":"ticker","body":{"type":"init","msg_id":1,"node_id":"ticker","node_ids":["ticker"]}}"#)
+            .await;
+        assert_eq!(harness.recv().await["body"]["type"], "init_ok");
+        for _ in 0..3 {
+            assert_eq!(harness.recv().await["body"]["echo"], "tick");
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_handlers_overlap() {
+        let mut harness =
+            Harness::init_concurrent::<ConcurrentTestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"ask"}}"#)
+            .await;
+        let question = harness.recv().await;
+        assert_eq!(question["dest"], "n2");
+        // This message is handled while the first handler waits for its answer
+        harness.send_line(KNOWN_ECHO_MSG).await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["echo"], "Please echo 35");
+        assert_eq!(resp["body"]["in_reply_to"], 1);
+        harness.respond(&question, answer()).await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["echo"], "answer");
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+    }
+
+    #[tokio::test]
+    async fn concurrent_handlers_are_limited() {
+        let config = ClientConfig {
+            max_concurrent_handlers: 1,
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_concurrent::<ConcurrentTestNode>(config).await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"ask"}}"#)
+            .await;
+        let question = harness.recv().await;
+        assert_eq!(question["dest"], "n2");
+        // The only handler slot is taken, so this message waits
+        harness.send_line(KNOWN_ECHO_MSG).await;
+        harness.respond(&question, answer()).await;
+        assert_eq!(harness.recv().await["body"]["in_reply_to"], 7);
+        assert_eq!(harness.recv().await["body"]["in_reply_to"], 1);
+        harness.input.shutdown().await.unwrap();
+        assert!(harness.handle.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn output_is_counted() {
+        let (ours, theirs) = tokio::io::duplex(1 << 16);
+        let (node_input, node_output) = split(theirs);
+        let (output, mut input) = split(ours);
+        let mut output = BufReader::new(output).lines();
+        input.write_all(KNOWN_INIT_MSG.as_bytes()).await.unwrap();
+        input.write_all(b"\n").await.unwrap();
+        let (mut client, _): (_, TestNode) = Client::with_transport(
+            BufReader::new(node_input),
+            node_output,
+            ClientConfig::default(),
+        )
+        .await
+        .unwrap();
+        for _ in 0..3 {
+            let msg = Message::new("n1", "c1", known_echo_ok_body());
+            client.send_msg(msg).await.unwrap();
+        }
+        let mut bytes = 0;
+        for _ in 0..4 {
+            bytes += output.next_line().await.unwrap().unwrap().len() as u64 + 1;
+        }
+        // The counters are updated once the batch has been flushed
+        while client.output_stats().messages < 4 {
+            tokio::task::yield_now().await;
+        }
+        let stats = client.output_stats();
+        assert_eq!(stats.messages, 4);
+        assert_eq!(stats.bytes, bytes);
+        assert!((1..=4).contains(&stats.flushes));
+    }
+
+    #[tokio::test]
+    async fn serialization_errors_are_returned() {
+        let (ours, theirs) = tokio::io::duplex(1 << 16);
+        let (node_input, node_output) = split(theirs);
+        let (output, mut input) = split(ours);
+        let mut output = BufReader::new(output).lines();
+        input.write_all(KNOWN_INIT_MSG.as_bytes()).await.unwrap();
+        input.write_all(b"\n").await.unwrap();
+        let (mut client, _): (_, TestNode) = Client::with_transport(
+            BufReader::new(node_input),
+            node_output,
+            ClientConfig::default(),
+        )
+        .await
+        .unwrap();
+        let body = UnserializableBody(HashMap::from([((1, 2), 3)]));
+        let res = client.send_msg(Message::new("n1", "c1", body)).await;
+        assert!(matches!(res, Err(Error::Serialize(_))));
+        // Nothing was written for the message, and the client can still send
+        let msg = Message::new("n1", "c1", known_echo_ok_body());
+        client.send_msg(msg).await.unwrap();
+        let init_ok = output.next_line().await.unwrap().unwrap();
+        assert!(init_ok.contains("init_ok"));
+        let echo_ok = output.next_line().await.unwrap().unwrap();
+        assert!(echo_ok.contains("echo_ok"));
+    }
+
+    #[tokio::test]
+    async fn outboxes_are_sent_in_order() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"fanout"}}"#,
+            )
+            .await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["type"], "echo_ok");
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        let note = harness.recv().await;
+        assert_eq!(note["body"]["type"], "echo");
+        assert_eq!(note["body"]["echo"], "fanned out");
+    }
+
+    #[tokio::test]
+    async fn duplicates_replay_their_replies() {
+        let config = ClientConfig {
+            dedup: Some(DedupConfig::default()),
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        let fanout =
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"fanout"}}"#;
+        harness.send_line(fanout).await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        assert_eq!(harness.recv().await["body"]["echo"], "fanned out");
+        harness.send_line(fanout).await;
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":8,"echo":"again"}}"#,
+            )
+            .await;
+        // The handler is not run again, so only the reply is sent
+        let replay = harness.recv().await;
+        assert_eq!(replay["body"]["echo"], "fanout");
+        assert_eq!(replay["body"]["in_reply_to"], 7);
+        assert_ne!(replay["body"]["msg_id"], resp["body"]["msg_id"]);
+        assert_eq!(harness.recv().await["body"]["in_reply_to"], 8);
+    }
+
+    #[tokio::test]
+    async fn duplicates_replay_their_errors() {
+        let config = ClientConfig {
+            dedup: Some(DedupConfig::default()),
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        let fail = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"fail"}}"#;
+        for _ in 0..2 {
+            harness.send_line(fail).await;
+            let resp = harness.recv().await;
+            assert_eq!(resp["body"]["type"], "error");
+            assert_eq!(resp["body"]["in_reply_to"], 7);
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicates_replay_replies_sent_through_the_sender() {
+        let config = ClientConfig {
+            dedup: Some(DedupConfig::default()),
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        let ask = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"ask"}}"#;
+        harness.send_line(ask).await;
+        let question = harness.recv().await;
+        assert_eq!(question["dest"], "n2");
+        let resp = harness.answer(&question, answer()).await;
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        harness.send_line(ask).await;
+        // The question is not asked again, and the answer is replayed
+        let replay = harness.recv().await;
+        assert_eq!(replay["dest"], "c1");
+        assert_eq!(replay["body"]["echo"], "answer");
+        assert_eq!(replay["body"]["in_reply_to"], 7);
+        assert_ne!(replay["body"]["msg_id"], resp["body"]["msg_id"]);
+    }
+
+    #[tokio::test]
+    async fn replies_are_not_deduplicated() {
+        let config = ClientConfig {
+            dedup: Some(DedupConfig::default()),
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        let stray = json!({
+            "src": "c1",
+            "dest": "n1",
+            "body": {"type": "echo_ok", "echo": "stray", "msg_id": 5, "in_reply_to": 1},
+        });
+        for _ in 0..2 {
+            harness.send(stray.clone()).await;
+            assert_eq!(harness.recv().await["body"]["echo"], "got stray");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn duplicates_expire() {
+        let config = ClientConfig {
+            dedup: Some(DedupConfig {
+                ttl: Duration::from_secs(1),
+                ..DedupConfig::default()
+            }),
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        let fanout =
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"fanout"}}"#;
+        harness.send_line(fanout).await;
+        assert_eq!(harness.recv().await["body"]["echo"], "fanout");
+        assert_eq!(harness.recv().await["body"]["echo"], "fanned out");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        // Still remembered, so only the reply is replayed
+        harness.send_line(fanout).await;
+        assert_eq!(harness.recv().await["body"]["echo"], "fanout");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        // Forgotten, so the handler runs again and the note is sent again
+        harness.send_line(fanout).await;
+        assert_eq!(harness.recv().await["body"]["echo"], "fanout");
+        assert_eq!(harness.recv().await["body"]["echo"], "fanned out");
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_forgotten() {
+        let config = ClientConfig {
+            dedup: Some(DedupConfig {
+                capacity: 1,
+                ..DedupConfig::default()
+            }),
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<TestNode>(config).await;
+        let fanout =
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"fanout"}}"#;
+        harness.send_line(fanout).await;
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":8,"echo":"again"}}"#,
+            )
+            .await;
+        harness.send_line(fanout).await;
+        let echoes = [
+            "fanout",
+            "fanned out",
+            "again",
+            // The first message was forgotten to make room for the second one, so it is handled
+            // again
+            "fanout",
+            "fanned out",
+        ];
+        for echo in echoes {
+            assert_eq!(harness.recv().await["body"]["echo"], echo);
+        }
+    }
+
+    #[tokio::test]
+    async fn outbound_messages_get_fresh_ids() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"fanout"}}"#,
+            )
+            .await;
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":8,"echo":"again"}}"#,
+            )
+            .await;
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(harness.recv().await["body"]["msg_id"].as_u64().unwrap());
+        }
+        // The init_ok message took the first id
+        assert!(ids[0] > 0);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn replies_are_filled_in() {
+        let mut harness = Harness::init_with::<AsyncTestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":42,"echo":"hi"}}"#)
+            .await;
+        let resp = harness.recv().await;
+        // The node replies with a made-up `in_reply_to`, which `into_response` replaces
+        assert_eq!(resp["body"]["in_reply_to"], 42);
+    }
+
+    #[tokio::test]
+    async fn only_replies_are_filled_in() {
+        let mut harness = Harness::init_with::<TestNode>(ClientConfig::default()).await;
+        harness
+            .send_line(
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7,"echo":"forward"}}"#,
+            )
+            .await;
+        let forwarded = harness.recv().await;
+        assert_eq!(forwarded["dest"], "c1");
+        assert_eq!(forwarded["body"]["type"], "echo_ok");
+        // The message goes back to the sender, but it was not made with `into_response`
+        assert_eq!(forwarded["body"]["in_reply_to"], 0);
     }
 }
@@ -0,0 +1,77 @@
+pub mod utils;
+
+#[cfg(test)]
+mod tests {
+    use aurora::{ClientConfig, Error, MalformedPolicy};
+
+    use tokio::io::AsyncWriteExt;
+
+    use super::utils::*;
+
+    #[tokio::test]
+    async fn malformed_lines_are_replied_to() {
+        let mut harness = Harness::init::<EchoNode>().await;
+        harness
+            .send_line(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7}}"#)
+            .await;
+        harness.send_line("not even json").await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["type"], "error");
+        assert_eq!(resp["body"]["code"], 12);
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        // The line that isn't JSON can't be replied to, but the node keeps going
+        harness.send_line(KNOWN_ECHO_MSG).await;
+        assert_eq!(harness.recv().await["body"]["type"], "echo_ok");
+    }
+
+    #[tokio::test]
+    async fn malformed_lines_can_abort() {
+        let config = ClientConfig {
+            malformed: MalformedPolicy::Abort,
+            ..ClientConfig::default()
+        };
+        let mut harness = Harness::init_with::<EchoNode>(config).await;
+        harness.send_line("not even json").await;
+        let res = harness.handle.await.unwrap();
+        let err = res.unwrap_err();
+        assert!(matches!(err, Error::Malformed { .. }));
+        assert!(err.is_malformed());
+        assert_eq!(err.line(), Some("not even json"));
+    }
+
+    #[tokio::test]
+    async fn second_inits_are_replied_to() {
+        let mut harness = Harness::init::<EchoNode>().await;
+        harness.send_line(KNOWN_INIT_MSG).await;
+        let resp = harness.recv().await;
+        assert_eq!(resp["body"]["type"], "error");
+        assert_eq!(resp["body"]["code"], 12);
+        assert_eq!(resp["body"]["in_reply_to"], 1);
+        assert_eq!(resp["body"]["text"], "node has already been initialized");
+    }
+
+    #[tokio::test]
+    async fn the_first_message_must_be_init() {
+        let mut harness = Harness::new::<EchoNode>(ClientConfig::default());
+        harness.send_line(KNOWN_INIT_OK_MSG).await;
+        let err = harness.handle.await.unwrap().unwrap_err();
+        assert!(matches!(err, Error::MissingInit(_)));
+        assert!(!err.is_malformed());
+        assert_eq!(err.line(), Some(KNOWN_INIT_OK_MSG));
+
+        // Lines that aren't init bodies at all are malformed
+        let mut harness = Harness::new::<EchoNode>(ClientConfig::default());
+        harness.send_line(KNOWN_ECHO_MSG).await;
+        let err = harness.handle.await.unwrap().unwrap_err();
+        assert!(matches!(err, Error::Malformed { .. }));
+    }
+
+    #[tokio::test]
+    async fn closing_the_input_before_init_is_an_error() {
+        let mut harness = Harness::new::<EchoNode>(ClientConfig::default());
+        harness.input.shutdown().await.unwrap();
+        let err = harness.handle.await.unwrap().unwrap_err();
+        assert!(matches!(err, Error::InputClosed));
+        assert_eq!(err.to_string(), "the input was closed");
+    }
+}
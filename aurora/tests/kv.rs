@@ -0,0 +1,255 @@
+pub mod utils;
+
+#[cfg(test)]
+mod tests {
+    use aurora::{GCounterBody, KvBody, KvError, LinKvClient, NodeContext, OneOf, SeqKvClient};
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    use super::utils::*;
+
+    /// Runs each command against `lin-kv`. Commands are "read <key>", "write <key> <value>",
+    /// "cas <key> <from> <to>", "create <key> <from> <to>", and "incr <key>". "fresh <key>" reads
+    /// the key from `seq-kv` instead.
+    struct Kv;
+
+    type KvNode = CommandNode<Kv>;
+
+    impl Commands for Kv {
+        type Body = KvBody;
+
+        fn init(_: &NodeContext<CommandBody<Self::Body>>) -> Self {
+            Self
+        }
+
+        async fn run(
+            &mut self,
+            ctx: &NodeContext<CommandBody<Self::Body>>,
+            command: &str,
+        ) -> anyhow::Result<String> {
+            let kv = LinKvClient::new(ctx);
+            let args = command.split(' ').collect::<Vec<_>>();
+            let res = match args[..] {
+                ["read", key] => kv.read::<_, u64>(key).await.map(|val| val.to_string()),
+                ["write", key, val] => kv
+                    .write(key, val.parse::<u64>()?)
+                    .await
+                    .map(|_| "ok".into()),
+                ["cas", key, from, to] => kv
+                    .cas(key, from.parse::<u64>()?, to.parse()?, false)
+                    .await
+                    .map(|_| "ok".into()),
+                ["create", key, from, to] => kv
+                    .cas(key, from.parse::<u64>()?, to.parse()?, true)
+                    .await
+                    .map(|_| "ok".into()),
+                ["incr", key] => kv
+                    .update(key, 0u64, |val| val + 1)
+                    .await
+                    .map(|val| val.to_string()),
+                ["fresh", key] => SeqKvClient::new(ctx)
+                    .read_fresh::<_, u64>(key)
+                    .await
+                    .map(|val| val.to_string()),
+                _ => anyhow::bail!("unknown command"),
+            };
+            Ok(match res {
+                Ok(echo) => echo,
+                Err(KvError::KeyDoesNotExist) => "missing".into(),
+                Err(KvError::PreconditionFailed) => "conflict".into(),
+                Err(KvError::Remote(err)) => format!("remote {}", err.code.code()),
+                Err(err) => format!("failed: {err}"),
+            })
+        }
+    }
+
+    /// Reads the "counter" key from `seq-kv` for every command. The node's bodies also include the
+    /// workload's `read_ok`, so the service's replies share a type with them.
+    struct Counter;
+
+    impl Commands for Counter {
+        type Body = OneOf<GCounterBody, KvBody>;
+
+        fn init(_: &NodeContext<CommandBody<Self::Body>>) -> Self {
+            Self
+        }
+
+        async fn run(
+            &mut self,
+            ctx: &NodeContext<CommandBody<Self::Body>>,
+            _: &str,
+        ) -> anyhow::Result<String> {
+            let value = SeqKvClient::new(ctx).read::<_, u64>("counter").await?;
+            Ok(value.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn kv_requests_are_typed() {
+        let mut harness = Harness::init::<KvNode>().await;
+        let req = harness.command("read x").await;
+        assert_eq!(req["dest"], "lin-kv");
+        assert_eq!(req["body"]["type"], "read");
+        assert_eq!(req["body"]["key"], "x");
+        let echo = harness
+            .reply(&req, json!({"type": "read_ok", "value": 5}))
+            .await;
+        assert_eq!(echo, "5");
+
+        let req = harness.command("write x 6").await;
+        assert_eq!(req["body"]["type"], "write");
+        assert_eq!(req["body"]["value"], 6);
+        let echo = harness.reply(&req, json!({"type": "write_ok"})).await;
+        assert_eq!(echo, "ok");
+
+        let req = harness.command("cas x 6 7").await;
+        assert_eq!(req["body"]["type"], "cas");
+        assert_eq!(req["body"]["from"], 6);
+        assert_eq!(req["body"]["to"], 7);
+        assert_eq!(req["body"].get("create_if_not_exists"), None);
+        let echo = harness.reply(&req, json!({"type": "cas_ok"})).await;
+        assert_eq!(echo, "ok");
+
+        let req = harness.command("create y 0 1").await;
+        assert_eq!(req["body"]["create_if_not_exists"], true);
+        let echo = harness.reply(&req, json!({"type": "cas_ok"})).await;
+        assert_eq!(echo, "ok");
+    }
+
+    #[tokio::test]
+    async fn kv_errors_are_typed() {
+        let mut harness = Harness::init::<KvNode>().await;
+        let req = harness.command("read x").await;
+        let echo = harness
+            .reply(&req, json!({"type": "error", "code": 20}))
+            .await;
+        assert_eq!(echo, "missing");
+
+        let req = harness.command("cas x 1 2").await;
+        let echo = harness
+            .reply(
+                &req,
+                json!({"type": "error", "code": 22, "text": "expected 1"}),
+            )
+            .await;
+        assert_eq!(echo, "conflict");
+
+        let req = harness.command("write x 1").await;
+        let echo = harness
+            .reply(&req, json!({"type": "error", "code": 11}))
+            .await;
+        assert_eq!(echo, "remote 11");
+
+        // Replies of the wrong type are caught
+        let req = harness.command("read x").await;
+        let echo = harness.reply(&req, json!({"type": "write_ok"})).await;
+        assert_eq!(
+            echo,
+            "failed: invalid key-value data: expected a `read_ok` reply but got `write_ok`"
+        );
+    }
+
+    #[tokio::test]
+    async fn updates_retry_on_conflict() {
+        let mut harness = Harness::init::<KvNode>().await;
+        let read = harness.command("incr x").await;
+        assert_eq!(read["body"]["type"], "read");
+        let cas = harness
+            .answer(&read, json!({"type": "read_ok", "value": 4}))
+            .await;
+        assert_eq!(cas["body"]["type"], "cas");
+        assert_eq!(cas["body"]["from"], 4);
+        assert_eq!(cas["body"]["to"], 5);
+
+        // Someone else got there first, so the key is read again
+        let read = harness
+            .answer(&cas, json!({"type": "error", "code": 22}))
+            .await;
+        assert_eq!(read["body"]["type"], "read");
+        let cas = harness
+            .answer(&read, json!({"type": "read_ok", "value": 6}))
+            .await;
+        assert_eq!(cas["body"]["from"], 6);
+        assert_eq!(cas["body"]["to"], 7);
+        let echo = harness.reply(&cas, json!({"type": "cas_ok"})).await;
+        assert_eq!(echo, "7");
+
+        // Missing keys start from the default
+        let read = harness.command("incr y").await;
+        let cas = harness
+            .answer(&read, json!({"type": "error", "code": 20}))
+            .await;
+        assert_eq!(cas["body"]["from"], 0);
+        assert_eq!(cas["body"]["to"], 1);
+        assert_eq!(cas["body"]["create_if_not_exists"], true);
+        let echo = harness.reply(&cas, json!({"type": "cas_ok"})).await;
+        assert_eq!(echo, "1");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn contended_updates_back_off_and_give_up() {
+        let mut harness = Harness::init::<KvNode>().await;
+        let start = Instant::now();
+        let mut reads = Vec::new();
+        let mut msg = harness.command("incr x").await;
+        // Every compare-and-set conflicts
+        loop {
+            msg = match msg["body"]["type"].as_str() {
+                Some("read") => {
+                    reads.push(Instant::now());
+                    let body = json!({"type": "read_ok", "value": 4});
+                    harness.answer(&msg, body).await
+                }
+                Some("cas") => {
+                    let body = json!({"type": "error", "code": 22});
+                    harness.answer(&msg, body).await
+                }
+                _ => break,
+            };
+        }
+        assert_eq!(msg["body"]["in_reply_to"], 7);
+        let echo = msg["body"]["echo"].as_str().unwrap();
+        assert!(echo.starts_with("failed: the key kept changing"), "{echo}");
+        // The update gives up shortly before its deadline
+        assert!(start.elapsed() > Duration::from_secs(4));
+        assert!(start.elapsed() <= Duration::from_secs(5));
+        // The waits between reads grow
+        let waits = reads.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+        assert!(waits[2] > waits[0], "{waits:?}");
+    }
+
+    #[tokio::test]
+    async fn fresh_reads_write_a_sentinel_first() {
+        let mut harness = Harness::init::<KvNode>().await;
+        let write = harness.command("fresh x").await;
+        assert_eq!(write["dest"], "seq-kv");
+        assert_eq!(write["body"]["type"], "write");
+        assert_eq!(write["body"]["key"], "aurora/barrier/n3");
+        let read = harness.answer(&write, json!({"type": "write_ok"})).await;
+        assert_eq!(read["dest"], "seq-kv");
+        assert_eq!(read["body"]["type"], "read");
+        assert_eq!(read["body"]["key"], "x");
+        let echo = harness
+            .reply(&read, json!({"type": "read_ok", "value": 3}))
+            .await;
+        assert_eq!(echo, "3");
+
+        // Every barrier writes a new value
+        let next = harness.command("fresh x").await;
+        assert_ne!(next["body"]["value"], write["body"]["value"]);
+    }
+
+    #[tokio::test]
+    async fn shared_reply_types_reach_the_service() {
+        let mut harness = Harness::init::<CommandNode<Counter>>().await;
+        let read = harness.command("read").await;
+        assert_eq!(read["dest"], "seq-kv");
+        assert_eq!(read["body"]["key"], "counter");
+        // The reply also fits the workload's `read_ok`, but it answers the client's RPC
+        let echo = harness
+            .reply(&read, json!({"type": "read_ok", "msg_id": 3, "value": 5}))
+            .await;
+        assert_eq!(echo, "5");
+    }
+}
@@ -0,0 +1,105 @@
+pub mod utils;
+
+/// Subscribers are installed per thread, so these tests have a file to themselves.
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use aurora::{EchoBody, Message, Node, NodeContext, Outbox, MESSAGE_TARGET};
+    use tracing::{info, subscriber::DefaultGuard};
+    use tracing_subscriber::{fmt::MakeWriter, EnvFilter};
+
+    use super::utils::*;
+
+    /// Collects everything that is logged.
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl Logs {
+        /// Logs everything that passes the filter to this collector until the guard is dropped.
+        fn install(&self, filter: &str) -> DefaultGuard {
+            let subscriber = tracing_subscriber::fmt()
+                .with_env_filter(EnvFilter::new(filter))
+                .with_writer(self.clone())
+                .with_ansi(false)
+                .finish();
+            tracing::subscriber::set_default(subscriber)
+        }
+
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Logs {
+        type Writer = Logs;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// Echos messages back and logs each one that it handles.
+    struct LoggingNode;
+
+    impl Node for LoggingNode {
+        type Body = EchoBody;
+
+        fn init(_: &NodeContext<Self::Body>) -> Self {
+            Self
+        }
+
+        fn handle_msg(
+            &mut self,
+            ctx: &NodeContext<Self::Body>,
+            msg: Message<Self::Body>,
+        ) -> anyhow::Result<Outbox<Self::Body>> {
+            info!("handling an echo");
+            EchoNode.handle_msg(ctx, msg)
+        }
+    }
+
+    #[tokio::test]
+    async fn message_logs_can_be_enabled_on_their_own() {
+        let logs = Logs::default();
+        let _guard = logs.install(&format!("{MESSAGE_TARGET}=debug,info"));
+        let mut harness = Harness::init::<LoggingNode>().await;
+        harness.send_line(KNOWN_ECHO_MSG).await;
+        assert_eq!(harness.recv().await["body"]["type"], "echo_ok");
+
+        let logs = logs.contents();
+        assert!(logs.contains("received message"));
+        assert!(logs.contains("sent message"));
+        assert!(logs.contains("type=echo_ok"));
+        // What the node logs while handling a message is tagged with the message
+        assert!(logs.contains("message{src=c1 msg_id=1}: logging::tests: handling an echo"));
+    }
+
+    #[tokio::test]
+    async fn message_logs_are_off_by_default() {
+        let logs = Logs::default();
+        let _guard = logs.install("info");
+        let mut harness = Harness::init::<LoggingNode>().await;
+        harness.send_line(KNOWN_ECHO_MSG).await;
+        assert_eq!(harness.recv().await["body"]["type"], "echo_ok");
+
+        let logs = logs.contents();
+        assert!(!logs.contains("received message"));
+        assert!(!logs.contains("sent message"));
+        assert!(logs.contains("handling an echo"));
+    }
+}
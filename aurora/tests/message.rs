@@ -2,7 +2,12 @@ pub mod utils;
 
 #[cfg(test)]
 mod tests {
-    use aurora::{BroadcastBody, EchoBody, IdBody, InitBody, Message, OrInit};
+    use aurora::{
+        either::Either, BroadcastBody, EchoBody, ErrorBody, IdBody, Inbound, InitBody, KvBody,
+        Message, MessageBody, MessageId, OneOf, OrError,
+    };
+    use serde_json::{json, Value};
+    use std::collections::HashSet;
 
     use super::utils::*;
 
@@ -73,7 +78,7 @@ mod tests {
     fn read_reponse_tests() {
         let msg = known_response(known_read_ok_body());
         let json = serde_json::to_string(&msg).unwrap();
-        assert_eq!(json, KNOWN_READ_OK_MSG);
+        assert!(unordered_json_eq(&json, KNOWN_READ_OK_MSG));
         let data: Message<BroadcastBody> = serde_json::from_str(&json).unwrap();
         assert_eq!(data, msg);
     }
@@ -82,7 +87,7 @@ mod tests {
     fn topology_request_tests() {
         let msg = known_request(known_topology_body());
         let json = serde_json::to_string(&msg).unwrap();
-        assert_eq!(json, KNOWN_TOPOLOGY_MSG);
+        assert!(unordered_json_eq(&json, KNOWN_TOPOLOGY_MSG));
         let data: Message<BroadcastBody> = serde_json::from_str(&json).unwrap();
         assert_eq!(data, msg);
     }
@@ -96,48 +101,268 @@ mod tests {
         assert_eq!(data, msg);
     }
 
-    /* ------ OrInit ------ */
     #[test]
-    fn no_mixed_signals_with_or_init() {
+    fn error_reponse_tests() {
+        let msg = known_response(known_error_body());
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, KNOWN_ERROR_MSG);
+        let data: Message<ErrorBody> = serde_json::from_str(&json).unwrap();
+        assert_eq!(data, msg);
+    }
+
+    /* ------ OrError ------ */
+    #[test]
+    fn no_mixed_signals_with_or_error() {
+        assert_eq!(
+            serde_json::from_str::<Message<OrError<EchoBody>>>(KNOWN_ECHO_OK_MSG).unwrap(),
+            known_response(OrError::Main(known_echo_ok_body()))
+        );
+        assert_eq!(
+            serde_json::from_str::<Message<OrError<EchoBody>>>(KNOWN_ERROR_MSG).unwrap(),
+            known_response(OrError::Error(known_error_body()))
+        );
+    }
+
+    /* ------ Envelope ------ */
+    #[test]
+    fn envelope_fields_are_kept() {
+        let json = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi"},"id":42,"trace":"abc"}"#;
+        let msg: Message<EchoBody> = serde_json::from_str(json).unwrap();
+        assert_eq!(msg.id, Some(42));
+        assert_eq!(msg.extra["trace"], "abc");
+        let round_trip: Value = serde_json::to_value(&msg).unwrap();
+        assert_eq!(round_trip, serde_json::from_str::<Value>(json).unwrap());
+        // Responses do not carry the request's envelope
+        let mut resp = msg;
+        resp.into_response(|_| ());
+        assert_eq!(resp.id, None);
+        assert!(resp.extra.is_empty());
+    }
+
+    #[test]
+    fn raw_bodies_have_ids() {
+        let mut body = json!({"type": "ping", "msg_id": 3, "in_reply_to": 0});
+        assert_eq!(body.msg_id(), Some(MessageId(3)));
+        assert_eq!(body.in_reply_to(), Some(MessageId(0)));
+        body.update_msg_id(MessageId(4));
+        body.update_in_reply_to(MessageId(5));
+        assert_eq!(body, json!({"type": "ping", "msg_id": 4, "in_reply_to": 5}));
+        // Raw bodies without an `in_reply_to` are not treated as responses
+        let mut body = json!({"type": "ping"});
+        body.update_in_reply_to(MessageId(5));
+        assert_eq!(body.in_reply_to(), None);
+    }
+
+    /* ------ Inbound ------ */
+    #[test]
+    fn no_mixed_signals_with_inbound() {
+        assert_eq!(
+            Inbound::<EchoBody>::from_line(KNOWN_ECHO_MSG).unwrap(),
+            Inbound::Main(known_request(OrError::Main(known_echo_body())))
+        );
+        assert_eq!(
+            Inbound::<EchoBody>::from_line(KNOWN_ECHO_OK_MSG).unwrap(),
+            Inbound::Main(known_response(OrError::Main(known_echo_ok_body())))
+        );
+        assert_eq!(
+            Inbound::<IdBody>::from_line(KNOWN_ID_MSG).unwrap(),
+            Inbound::Main(known_request(OrError::Main(known_id_body())))
+        );
+        assert_eq!(
+            Inbound::<IdBody>::from_line(KNOWN_ID_OK_MSG).unwrap(),
+            Inbound::Main(known_response(OrError::Main(known_id_ok_body())))
+        );
+        assert_eq!(
+            Inbound::<BroadcastBody>::from_line(KNOWN_BROADCAST_MSG).unwrap(),
+            Inbound::Main(known_request(OrError::Main(known_broadcast_body())))
+        );
+        assert_eq!(
+            Inbound::<BroadcastBody>::from_line(KNOWN_BROADCAST_OK_MSG).unwrap(),
+            Inbound::Main(known_response(OrError::Main(known_broadcast_ok_body())))
+        );
+        assert_eq!(
+            Inbound::<BroadcastBody>::from_line(KNOWN_READ_MSG).unwrap(),
+            Inbound::Main(known_request(OrError::Main(known_read_body())))
+        );
         assert_eq!(
-            serde_json::from_str::<OrInit<EchoBody>>(KNOWN_ECHO_MSG).unwrap(),
-            OrInit::Main(known_request(known_echo_body()))
+            Inbound::<BroadcastBody>::from_line(KNOWN_READ_OK_MSG).unwrap(),
+            Inbound::Main(known_response(OrError::Main(known_read_ok_body())))
         );
         assert_eq!(
-            serde_json::from_str::<OrInit<EchoBody>>(KNOWN_ECHO_OK_MSG).unwrap(),
-            OrInit::Main(known_response(known_echo_ok_body()))
+            Inbound::<BroadcastBody>::from_line(KNOWN_TOPOLOGY_MSG).unwrap(),
+            Inbound::Main(known_request(OrError::Main(known_topology_body())))
         );
         assert_eq!(
-            serde_json::from_str::<OrInit<IdBody>>(KNOWN_ID_MSG).unwrap(),
-            OrInit::Main(known_request(known_id_body()))
+            Inbound::<BroadcastBody>::from_line(KNOWN_TOPOLOGY_OK_MSG).unwrap(),
+            Inbound::Main(known_response(OrError::Main(known_topology_ok_body())))
         );
+    }
+
+    #[test]
+    fn inbound_dispatches_on_type() {
         assert_eq!(
-            serde_json::from_str::<OrInit<IdBody>>(KNOWN_ID_OK_MSG).unwrap(),
-            OrInit::Main(known_response(known_id_ok_body()))
+            Inbound::<EchoBody>::from_line(KNOWN_INIT_MSG).unwrap(),
+            Inbound::Init(known_request(known_init_body()))
         );
         assert_eq!(
-            serde_json::from_str::<OrInit<BroadcastBody>>(KNOWN_BROADCAST_MSG).unwrap(),
-            OrInit::Main(known_request(known_broadcast_body()))
+            Inbound::<EchoBody>::from_line(KNOWN_ERROR_MSG).unwrap(),
+            Inbound::Main(known_response(OrError::Error(known_error_body())))
         );
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":3}}"#;
         assert_eq!(
-            serde_json::from_str::<OrInit<BroadcastBody>>(KNOWN_BROADCAST_OK_MSG).unwrap(),
-            OrInit::Main(known_response(known_broadcast_ok_body()))
+            Inbound::<EchoBody>::from_line(line).unwrap(),
+            Inbound::Main(known_request(OrError::Unknown(
+                json!({"type": "ping", "msg_id": 3})
+            )))
+        );
+    }
+
+    #[test]
+    fn inbound_errors_name_the_field() {
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":7}}"#;
+        let err = Inbound::<EchoBody>::from_line(line)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("invalid `echo` body: missing field `echo`"),
+            "{err}"
         );
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":"7","echo":""}}"#;
+        let err = Inbound::<EchoBody>::from_line(line)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("invalid `echo` body: invalid type: string"),
+            "{err}"
+        );
+        let line = r#"{"src":"c1","dest":"n1","body":{"msg_id":7}}"#;
+        let err = Inbound::<EchoBody>::from_line(line)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("invalid body: missing field `type`"),
+            "{err}"
+        );
+        let line = r#"{"src":"c1","body":{"type":"echo"}}"#;
+        let err = Inbound::<EchoBody>::from_line(line)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("missing field `dest`"), "{err}");
+    }
+
+    type Composed = OneOf<EchoBody, OneOf<BroadcastBody, IdBody>>;
+
+    #[test]
+    fn body_types_know_their_types() {
+        assert_eq!(EchoBody::knows_type("echo_ok"), Some(true));
+        assert_eq!(EchoBody::knows_type("generate"), Some(false));
+        assert_eq!(Composed::knows_type("generate"), Some(true));
+        assert_eq!(Composed::knows_type("ping"), Some(false));
+        assert_eq!(OrError::<EchoBody>::knows_type("error"), Some(true));
+        assert_eq!(Value::knows_type("ping"), Some(true));
+
+        // The types that a composed body does not know are unknown rather than malformed
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":3}}"#;
+        assert!(matches!(
+            Inbound::<Composed>::from_line(line).unwrap(),
+            Inbound::Main(Message {
+                body: OrError::Unknown(_),
+                ..
+            })
+        ));
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"generate_ok"}}"#;
+        let err = Inbound::<Composed>::from_line(line)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("invalid `generate_ok` body"), "{err}");
+    }
+
+    #[test]
+    fn composed_bodies_use_the_inner_format() {
+        let body = Composed::right(OneOf::left(known_broadcast_body()));
+        let json = serde_json::to_string(&body).unwrap();
+        assert_eq!(json, KNOWN_BROADCAST_BODY);
+        assert_eq!(serde_json::from_str::<Composed>(&json).unwrap(), body);
+        let body = Composed::right(OneOf::right(known_id_body()));
+        let json = serde_json::to_string(&body).unwrap();
+        assert_eq!(json, KNOWN_ID_BODY);
+        assert_eq!(serde_json::from_str::<Composed>(&json).unwrap(), body);
+        assert_eq!(body.msg_id(), Some(MessageId(1)));
+        let mut body = Composed::left(known_echo_body());
+        body.update_msg_id(MessageId(9));
+        assert_eq!(body.msg_id(), Some(MessageId(9)));
+    }
+
+    #[test]
+    fn composed_bodies_are_read_by_type() {
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"generate","msg_id":1}}"#;
         assert_eq!(
-            serde_json::from_str::<OrInit<BroadcastBody>>(KNOWN_READ_MSG).unwrap(),
-            OrInit::Main(known_request(known_read_body()))
+            Inbound::<Composed>::from_line(line).unwrap(),
+            Inbound::Main(known_request(OrError::Main(OneOf::right(OneOf::right(
+                known_id_body()
+            )))))
         );
+        // Types that no body knows are still unknown
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":3}}"#;
         assert_eq!(
-            serde_json::from_str::<OrInit<BroadcastBody>>(KNOWN_READ_OK_MSG).unwrap(),
-            OrInit::Main(known_response(known_read_ok_body()))
+            Inbound::<Composed>::from_line(line).unwrap(),
+            Inbound::Main(known_request(OrError::Unknown(
+                json!({"type": "ping", "msg_id": 3})
+            )))
+        );
+        // And bad bodies name the field that could not be parsed
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"broadcast","msg_id":7}}"#;
+        let err = Inbound::<Composed>::from_line(line)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("invalid `broadcast` body: missing field `message`"),
+            "{err}"
         );
+    }
+
+    #[test]
+    fn composed_bodies_can_share_types() {
+        type Shared = OneOf<BroadcastBody, KvBody>;
+        // Both bodies have a `read_ok`, so each is read as whichever body it fits
+        let json = r#"{"type":"read_ok","msg_id":2,"in_reply_to":1,"messages":[3]}"#;
+        assert_eq!(
+            serde_json::from_str::<Shared>(json).unwrap(),
+            Shared::left(BroadcastBody::ReadOk {
+                msg_id: MessageId(2),
+                in_reply_to: MessageId(1),
+                messages: HashSet::from([3]),
+            })
+        );
+        let json = r#"{"type":"read_ok","in_reply_to":1,"value":[3]}"#;
         assert_eq!(
-            serde_json::from_str::<OrInit<BroadcastBody>>(KNOWN_TOPOLOGY_MSG).unwrap(),
-            OrInit::Main(known_request(known_topology_body()))
+            serde_json::from_str::<Shared>(json).unwrap(),
+            Shared::right(KvBody::ReadOk {
+                msg_id: None,
+                in_reply_to: MessageId(1),
+                value: json!([3]),
+            })
+        );
+        // Bodies that fit neither are reported with the error for the left body
+        let line = r#"{"src":"c1","dest":"n1","body":{"type":"read_ok","in_reply_to":1}}"#;
+        let err = Inbound::<Shared>::from_line(line).unwrap_err().to_string();
+        assert!(
+            err.starts_with("invalid `read_ok` body: missing field `msg_id`"),
+            "{err}"
         );
+    }
+
+    #[test]
+    fn composed_messages_split() {
+        let msg = known_request(Composed::left(known_echo_body()));
+        assert_eq!(msg.split(), Either::Left(known_request(known_echo_body())));
+        let msg = known_request(Composed::right(OneOf::left(known_broadcast_body())));
+        let Either::Right(msg) = msg.split() else {
+            panic!("expected the right body")
+        };
         assert_eq!(
-            serde_json::from_str::<OrInit<BroadcastBody>>(KNOWN_TOPOLOGY_OK_MSG).unwrap(),
-            OrInit::Main(known_response(known_topology_ok_body()))
+            msg.split(),
+            Either::Left(known_request(known_broadcast_body()))
         );
     }
 }
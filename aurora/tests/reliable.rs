@@ -0,0 +1,156 @@
+pub mod utils;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use aurora::{
+        Delivery, EchoBody, Message, MessageBody, MessageId, NodeContext, Reliable, RetryPolicy,
+        RpcError,
+    };
+    use serde_json::{json, Value};
+
+    fn context() -> NodeContext<EchoBody> {
+        NodeContext::detached("n1", vec![String::from("n1"), String::from("n2")])
+    }
+
+    fn request(ctx: &NodeContext<EchoBody>) -> Message<EchoBody> {
+        ctx.message(
+            "n2",
+            EchoBody::Echo {
+                msg_id: MessageId::default(),
+                echo: String::from("hello"),
+            },
+        )
+    }
+
+    fn reply(src: &str, in_reply_to: MessageId) -> Message<EchoBody> {
+        Message::new(
+            src,
+            "n1",
+            EchoBody::EchoOk {
+                echo: String::from("hello"),
+                msg_id: MessageId(50),
+                in_reply_to,
+            },
+        )
+    }
+
+    /// Advances the clock and returns the ids of the requests that were retransmitted.
+    async fn resent_after(
+        reliable: &mut Reliable<EchoBody>,
+        ctx: &NodeContext<EchoBody>,
+        millis: u64,
+    ) -> Vec<MessageId> {
+        tokio::time::advance(Duration::from_millis(millis)).await;
+        let (outbox, expired) = reliable.poll(ctx);
+        assert!(expired.is_empty());
+        outbox
+            .into_iter()
+            .map(|msg| msg.body.msg_id().unwrap())
+            .collect()
+    }
+
+    fn no_jitter() -> RetryPolicy {
+        RetryPolicy {
+            initial: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(400),
+            multiplier: 2,
+            jitter: Duration::ZERO,
+            deadline: None,
+        }
+    }
+
+    #[test]
+    fn bodies_without_ids_are_not_tracked() {
+        let ctx = NodeContext::<Value>::detached("n1", vec![String::from("n1")]);
+        let mut reliable = Reliable::default();
+        let msg = ctx.message("n2", json!({"type": "note"}));
+        assert_eq!(reliable.track(&ctx, msg), Err(RpcError::MissingMsgId));
+        // No id was used up
+        assert_eq!(ctx.next_id(), MessageId(0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retransmits_with_backoff() {
+        let ctx = context();
+        let mut reliable = Reliable::new(no_jitter());
+        let id = reliable
+            .track(&ctx, request(&ctx))
+            .unwrap()
+            .body
+            .msg_id()
+            .unwrap();
+        assert!(resent_after(&mut reliable, &ctx, 99).await.is_empty());
+        assert_eq!(resent_after(&mut reliable, &ctx, 1).await, [id]);
+        assert!(resent_after(&mut reliable, &ctx, 199).await.is_empty());
+        assert_eq!(resent_after(&mut reliable, &ctx, 1).await, [id]);
+        // The backoff is capped
+        assert_eq!(resent_after(&mut reliable, &ctx, 400).await, [id]);
+        assert_eq!(resent_after(&mut reliable, &ctx, 400).await, [id]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn jitter_is_bounded() {
+        let ctx = context();
+        let mut reliable = Reliable::new(RetryPolicy {
+            jitter: Duration::from_millis(50),
+            ..no_jitter()
+        });
+        reliable.track(&ctx, request(&ctx)).unwrap();
+        assert!(resent_after(&mut reliable, &ctx, 99).await.is_empty());
+        assert_eq!(resent_after(&mut reliable, &ctx, 51).await.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replies_stop_retransmissions() {
+        let ctx = context();
+        let mut reliable = Reliable::new(no_jitter());
+        let msg = reliable.track(&ctx, request(&ctx)).unwrap();
+        let id = msg.body.msg_id().unwrap();
+        assert_eq!(resent_after(&mut reliable, &ctx, 100).await, [id]);
+
+        // Replies from other nodes and to other messages do not count
+        assert_eq!(reliable.ack(&reply("n3", id)), None);
+        assert_eq!(reliable.ack(&reply("n2", MessageId(id.0 + 1))), None);
+        assert_eq!(
+            reliable.ack(&reply("n2", id)),
+            Some(Delivery::Delivered {
+                request: msg,
+                attempts: 2
+            })
+        );
+        assert!(reliable.is_empty());
+        assert!(resent_after(&mut reliable, &ctx, 1000).await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadlines_give_up() {
+        let ctx = context();
+        let mut reliable = Reliable::new(RetryPolicy {
+            deadline: Some(Duration::from_millis(250)),
+            ..no_jitter()
+        });
+        let msg = reliable.track(&ctx, request(&ctx)).unwrap();
+        assert_eq!(
+            reliable.next_wakeup(),
+            Some(ctx.now() + Duration::from_millis(100))
+        );
+        assert_eq!(resent_after(&mut reliable, &ctx, 100).await.len(), 1);
+        assert_eq!(
+            reliable.next_wakeup(),
+            Some(ctx.now() + Duration::from_millis(150))
+        );
+        tokio::time::advance(Duration::from_millis(150)).await;
+        let (outbox, expired) = reliable.poll(&ctx);
+        assert!(outbox.is_empty());
+        assert_eq!(
+            expired,
+            [Delivery::Expired {
+                request: msg,
+                attempts: 2
+            }]
+        );
+        assert!(reliable.is_empty());
+    }
+}
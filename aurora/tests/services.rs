@@ -0,0 +1,163 @@
+pub mod utils;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use aurora::{
+        Cluster, ClusterClient, KvBody, LinKvClient, LinKvService, LinTsoService, LwwKvService,
+        NodeContext, SeqKvService, LIN_KV, LIN_TSO, LWW_KV, LWW_KV_MERGE_DELAY_VAR, SEQ_KV,
+        SEQ_KV_STALENESS_VAR,
+    };
+    use serde_json::{json, Value};
+
+    use super::utils::*;
+
+    fn cluster() -> Cluster {
+        Cluster::new(vec![String::from("n1")])
+    }
+
+    /// Sends a request and returns the reply's body.
+    async fn call(client: &mut ClusterClient, dest: &str, mut body: Value) -> Value {
+        body["msg_id"] = json!(1);
+        client.send(dest, body);
+        let reply = client.recv().await.unwrap();
+        assert_eq!(reply["src"], dest);
+        assert_eq!(reply["body"]["in_reply_to"], 1);
+        reply["body"].clone()
+    }
+
+    async fn read(client: &mut ClusterClient, service: &str, key: &str) -> Value {
+        let body = call(client, service, json!({"type": "read", "key": key})).await;
+        match body["type"].as_str() {
+            Some("read_ok") => body["value"].clone(),
+            _ => body["code"].clone(),
+        }
+    }
+
+    async fn write(client: &mut ClusterClient, service: &str, key: &str, value: Value) {
+        let body = json!({"type": "write", "key": key, "value": value});
+        assert_eq!(call(client, service, body).await["type"], "write_ok");
+    }
+
+    #[tokio::test]
+    async fn lin_kv_applies_requests_in_order() {
+        let mut cluster = cluster();
+        cluster.add::<LinKvService>(LIN_KV);
+        let mut client = cluster.client("c1");
+
+        // Missing keys are errors
+        assert_eq!(read(&mut client, LIN_KV, "x").await, 20);
+        write(&mut client, LIN_KV, "x", json!(1)).await;
+        assert_eq!(read(&mut client, LIN_KV, "x").await, 1);
+
+        let cas = json!({"type": "cas", "key": "x", "from": 2, "to": 3});
+        let body = call(&mut client, LIN_KV, cas).await;
+        assert_eq!(body["type"], "error");
+        assert_eq!(body["code"], 22);
+        let cas = json!({"type": "cas", "key": "x", "from": 1, "to": 3});
+        assert_eq!(call(&mut client, LIN_KV, cas).await["type"], "cas_ok");
+        assert_eq!(read(&mut client, LIN_KV, "x").await, 3);
+
+        let create = json!({"type": "cas", "key": "y", "from": 0, "to": 1});
+        assert_eq!(call(&mut client, LIN_KV, create.clone()).await["code"], 20);
+        let mut create = create;
+        create["create_if_not_exists"] = json!(true);
+        assert_eq!(call(&mut client, LIN_KV, create).await["type"], "cas_ok");
+        assert_eq!(read(&mut client, LIN_KV, "y").await, 1);
+    }
+
+    #[tokio::test]
+    async fn seq_kv_reads_are_stale_but_monotonic() {
+        let mut cluster = cluster();
+        // Every read can see any of the writes
+        std::env::set_var(SEQ_KV_STALENESS_VAR, "60000");
+        cluster.add::<SeqKvService>(SEQ_KV);
+        let mut writer = cluster.client("c1");
+        let mut reader = cluster.client("c2");
+        for i in 1..=5 {
+            write(&mut writer, SEQ_KV, "x", json!(i)).await;
+        }
+        // Writers see their own writes
+        assert_eq!(read(&mut writer, SEQ_KV, "x").await, 5);
+
+        // Other clients can see old values, but never go back in time
+        let mut last = 0;
+        for _ in 0..20 {
+            let val = read(&mut reader, SEQ_KV, "x").await;
+            let val = if val == 20 { 0 } else { val.as_u64().unwrap() };
+            assert!(val >= last);
+            last = val;
+        }
+
+        // A write catches the client up to the latest state
+        write(&mut reader, SEQ_KV, "barrier", json!("c2")).await;
+        assert_eq!(read(&mut reader, SEQ_KV, "x").await, 5);
+    }
+
+    #[tokio::test]
+    async fn lww_kv_merges_after_a_delay() {
+        let mut cluster = cluster();
+        std::env::set_var(LWW_KV_MERGE_DELAY_VAR, "50");
+        cluster.add::<LwwKvService>(LWW_KV);
+        let mut first = cluster.client("c1");
+        let mut second = cluster.client("c2");
+        write(&mut first, LWW_KV, "x", json!(1)).await;
+        assert_eq!(read(&mut first, LWW_KV, "x").await, 1);
+        assert_eq!(read(&mut second, LWW_KV, "x").await, 20);
+
+        write(&mut second, LWW_KV, "x", json!(2)).await;
+        assert_eq!(read(&mut first, LWW_KV, "x").await, 1);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        // The last write wins once the writes merge
+        assert_eq!(read(&mut first, LWW_KV, "x").await, 2);
+        assert_eq!(read(&mut second, LWW_KV, "x").await, 2);
+    }
+
+    #[tokio::test]
+    async fn lin_tso_counts_up() {
+        let mut cluster = cluster();
+        cluster.add::<LinTsoService>(LIN_TSO);
+        let mut client = cluster.client("c1");
+        for expected in 0..3 {
+            let body = call(&mut client, LIN_TSO, json!({"type": "ts"})).await;
+            assert_eq!(body["type"], "ts_ok");
+            assert_eq!(body["ts"], expected);
+        }
+    }
+
+    /// Increments a counter in `lin-kv` for each command and echos back the counter's new value.
+    struct Counter;
+
+    impl Commands for Counter {
+        type Body = KvBody;
+
+        fn init(_: &NodeContext<CommandBody<Self::Body>>) -> Self {
+            Self
+        }
+
+        async fn run(
+            &mut self,
+            ctx: &NodeContext<CommandBody<Self::Body>>,
+            _: &str,
+        ) -> anyhow::Result<String> {
+            let count = LinKvClient::new(ctx)
+                .update("count", 0u64, |val| val + 1)
+                .await?;
+            Ok(count.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn nodes_use_services_in_a_cluster() {
+        let mut cluster = Cluster::new(vec![String::from("n1"), String::from("n2")]);
+        cluster.add::<LinKvService>(LIN_KV);
+        cluster.add::<CommandNode<Counter>>("n1");
+        cluster.add::<CommandNode<Counter>>("n2");
+        let mut client = cluster.client("c1");
+        for (i, node) in ["n1", "n2", "n1"].into_iter().enumerate() {
+            let body = call(&mut client, node, json!({"type": "echo", "echo": ""})).await;
+            assert_eq!(body["echo"], (i + 1).to_string());
+        }
+    }
+}
@@ -0,0 +1,35 @@
+pub mod utils;
+
+/// Termination signals go to the whole process, so this test has a file to itself.
+#[cfg(all(test, unix))]
+mod tests {
+    use aurora::ClientConfig;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    use super::utils::*;
+
+    #[tokio::test]
+    async fn terminate_handling_can_be_turned_off() {
+        // Listening here as well means that the signal can never kill the test itself
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        let handled = Harness::init::<EchoNode>().await;
+        let config = ClientConfig {
+            handle_terminate: false,
+            ..ClientConfig::default()
+        };
+        let mut ignored = Harness::init_with::<EchoNode>(config).await;
+
+        let status = std::process::Command::new("kill")
+            .args(["-TERM", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        terminate.recv().await;
+
+        // The node that handles the signal stops, and the other one keeps going
+        assert!(handled.handle.await.unwrap().is_ok());
+        ignored.send_line(KNOWN_ECHO_MSG).await;
+        assert_eq!(ignored.recv().await["body"]["type"], "echo_ok");
+        assert!(!ignored.handle.is_finished());
+    }
+}
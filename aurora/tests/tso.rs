@@ -0,0 +1,171 @@
+pub mod utils;
+
+#[cfg(test)]
+mod tests {
+    use aurora::{KvBody, LinKvClient, NodeContext, OneOf, TsoBody, TsoClient};
+    use serde_json::{json, Value};
+
+    use super::utils::*;
+
+    /// Echos back a timestamp for each command. "ts" asks the oracle for every timestamp, and
+    /// "batch" uses a client that reserves three at a time.
+    struct Tso {
+        batched: TsoClient<CommandBody<TsoBody>>,
+    }
+
+    type TsoNode = CommandNode<Tso>;
+
+    impl Commands for Tso {
+        type Body = TsoBody;
+
+        fn init(ctx: &NodeContext<CommandBody<Self::Body>>) -> Self {
+            Self {
+                batched: TsoClient::batched(ctx, 3),
+            }
+        }
+
+        async fn run(
+            &mut self,
+            ctx: &NodeContext<CommandBody<Self::Body>>,
+            command: &str,
+        ) -> anyhow::Result<String> {
+            let res = match command {
+                "ts" => TsoClient::new(ctx).ts().await,
+                "batch" => self.batched.ts().await,
+                _ => anyhow::bail!("unknown command"),
+            };
+            Ok(match res {
+                Ok(ts) => ts.to_string(),
+                Err(err) => format!("failed: {err}"),
+            })
+        }
+    }
+
+    /// Echos back a timestamp from the oracle for every command. Raw bodies know every type, so
+    /// the oracle's replies would be read as raw bodies if they were not replies to the client's
+    /// RPCs.
+    struct Raw;
+
+    impl Commands for Raw {
+        type Body = OneOf<Value, TsoBody>;
+
+        fn init(_: &NodeContext<CommandBody<Self::Body>>) -> Self {
+            Self
+        }
+
+        async fn run(
+            &mut self,
+            ctx: &NodeContext<CommandBody<Self::Body>>,
+            _: &str,
+        ) -> anyhow::Result<String> {
+            Ok(TsoClient::new(ctx).ts().await?.to_string())
+        }
+    }
+
+    /// Asks the oracle for a timestamp, writes it to `lin-kv`, and echos it back. The key-value
+    /// body is on the left of the timestamp body, so the clients find their bodies on both sides.
+    struct Stamp;
+
+    impl Commands for Stamp {
+        type Body = OneOf<KvBody, TsoBody>;
+
+        fn init(_: &NodeContext<CommandBody<Self::Body>>) -> Self {
+            Self
+        }
+
+        async fn run(
+            &mut self,
+            ctx: &NodeContext<CommandBody<Self::Body>>,
+            _: &str,
+        ) -> anyhow::Result<String> {
+            let ts = TsoClient::new(ctx).ts().await?;
+            LinKvClient::new(ctx).write("ts", ts).await?;
+            Ok(ts.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn timestamps_are_typed() {
+        let mut harness = Harness::init::<TsoNode>().await;
+        let req = harness.command("ts").await;
+        assert_eq!(req["dest"], "lin-tso");
+        assert_eq!(req["body"]["type"], "ts");
+        let echo = harness.reply(&req, json!({"type": "ts_ok", "ts": 5})).await;
+        assert_eq!(echo, "5");
+
+        let req = harness.command("ts").await;
+        let echo = harness
+            .reply(&req, json!({"type": "error", "code": 11}))
+            .await;
+        assert!(echo
+            .as_str()
+            .unwrap()
+            .starts_with("failed: the request failed"));
+    }
+
+    #[tokio::test]
+    async fn batches_reserve_ranges() {
+        let mut harness = Harness::init::<TsoNode>().await;
+        let req = harness.command("batch").await;
+        let echo = harness.reply(&req, json!({"type": "ts_ok", "ts": 2})).await;
+        assert_eq!(echo, "6");
+
+        // The rest of the range is handed out without asking the oracle
+        for expected in ["7", "8"] {
+            let resp = harness.command("batch").await;
+            assert_eq!(resp["body"]["type"], "echo_ok");
+            assert_eq!(resp["body"]["echo"], expected);
+        }
+
+        let req = harness.command("batch").await;
+        assert_eq!(req["dest"], "lin-tso");
+        let echo = harness.reply(&req, json!({"type": "ts_ok", "ts": 4})).await;
+        assert_eq!(echo, "12");
+    }
+
+    #[tokio::test]
+    async fn batches_that_overflow_fail() {
+        let mut harness = Harness::init::<TsoNode>().await;
+        // The first range would end just past `u64::MAX`, and the second would start past it
+        for ts in [u64::MAX / 3, u64::MAX / 2] {
+            let req = harness.command("batch").await;
+            let echo = harness
+                .reply(&req, json!({"type": "ts_ok", "ts": ts}))
+                .await;
+            assert!(echo
+                .as_str()
+                .unwrap()
+                .starts_with("failed: invalid timestamp data"));
+        }
+
+        // Failed ranges are not kept, so the next timestamp asks the oracle again
+        let req = harness.command("batch").await;
+        assert_eq!(req["dest"], "lin-tso");
+        let echo = harness.reply(&req, json!({"type": "ts_ok", "ts": 1})).await;
+        assert_eq!(echo, "3");
+    }
+
+    #[tokio::test]
+    async fn nodes_can_use_several_services() {
+        let mut harness = Harness::init::<CommandNode<Stamp>>().await;
+        let req = harness.command("stamp").await;
+        assert_eq!(req["dest"], "lin-tso");
+        let req = harness
+            .answer(&req, json!({"type": "ts_ok", "ts": 5}))
+            .await;
+        assert_eq!(req["dest"], "lin-kv");
+        assert_eq!(req["body"]["type"], "write");
+        assert_eq!(req["body"]["value"], 5);
+        let echo = harness.reply(&req, json!({"type": "write_ok"})).await;
+        assert_eq!(echo, "5");
+    }
+
+    #[tokio::test]
+    async fn oracle_replies_are_read_as_tso_bodies() {
+        let mut harness = Harness::init::<CommandNode<Raw>>().await;
+        let req = harness.command("ts").await;
+        assert_eq!(req["dest"], "lin-tso");
+        let echo = harness.reply(&req, json!({"type": "ts_ok", "ts": 5})).await;
+        assert_eq!(echo, "5");
+    }
+}
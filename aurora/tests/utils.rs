@@ -1,5 +1,19 @@
-use aurora::{BroadcastBody, EchoBody, IdBody, InitBody, Message, MessageBody, MessageId};
+use std::future::Future;
+
+use aurora::{
+    either::Either, main_loop_concurrent_with_transport, main_loop_with_transport, AsyncNode,
+    BroadcastBody, ClientConfig, ConcurrentNode, EchoBody, Error, ErrorBody, ErrorCode,
+    GCounterBody, IdBody, InitBody, Message, MessageBody, MessageId, Node, NodeContext, OneOf,
+    Outbox,
+};
 use const_format::formatcp;
+use serde_json::{json, Value};
+use tokio::{
+    io::{
+        split, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf,
+    },
+    task::JoinHandle,
+};
 
 /* ------ Init ------ */
 pub const KNOWN_INIT_BODY: &str =
@@ -42,15 +56,20 @@ pub fn known_echo_ok_body() -> EchoBody {
 }
 
 /* ------ Ids ------ */
-pub const KNOWN_ID_BODY: &str = r#"{"type":"generate"}"#;
-pub const KNOWN_ID_OK_BODY: &str = r#"{"type":"generate_ok","id":"123"}"#;
+pub const KNOWN_ID_BODY: &str = r#"{"type":"generate","msg_id":1}"#;
+pub const KNOWN_ID_OK_BODY: &str =
+    r#"{"type":"generate_ok","msg_id":2,"in_reply_to":1,"id":"123"}"#;
 
 pub fn known_id_body() -> IdBody {
-    IdBody::Generate
+    IdBody::Generate {
+        msg_id: MessageId(1),
+    }
 }
 
 pub fn known_id_ok_body() -> IdBody {
     IdBody::GenerateOk {
+        msg_id: MessageId(2),
+        in_reply_to: MessageId(1),
         id: 123.to_string(),
     }
 }
@@ -87,7 +106,7 @@ pub fn known_read_body() -> BroadcastBody {
 
 pub fn known_read_ok_body() -> BroadcastBody {
     BroadcastBody::ReadOk {
-        msg_id: Some(MessageId(2)),
+        msg_id: MessageId(2),
         in_reply_to: MessageId(1),
         messages: [1, 8, 72, 25].into_iter().collect(),
     }
@@ -117,6 +136,47 @@ pub fn known_topology_ok_body() -> BroadcastBody {
     }
 }
 
+/* ------ G-Counter ------ */
+pub const KNOWN_ADD_BODY: &str = r#"{"type":"add","msg_id":1,"delta":5}"#;
+pub const KNOWN_ADD_OK_BODY: &str = r#"{"type":"add_ok","msg_id":2,"in_reply_to":1}"#;
+pub const KNOWN_COUNTER_READ_OK_BODY: &str =
+    r#"{"type":"read_ok","msg_id":2,"in_reply_to":1,"value":12}"#;
+
+pub fn known_add_body() -> GCounterBody {
+    GCounterBody::Add {
+        msg_id: MessageId(1),
+        delta: 5,
+    }
+}
+
+pub fn known_add_ok_body() -> GCounterBody {
+    GCounterBody::AddOk {
+        msg_id: MessageId(2),
+        in_reply_to: MessageId(1),
+    }
+}
+
+pub fn known_counter_read_ok_body() -> GCounterBody {
+    GCounterBody::ReadOk {
+        msg_id: MessageId(2),
+        in_reply_to: MessageId(1),
+        value: 12,
+    }
+}
+
+/* ------ Error ------ */
+pub const KNOWN_ERROR_BODY: &str =
+    r#"{"type":"error","in_reply_to":1,"code":10,"text":"unknown body type"}"#;
+
+pub fn known_error_body() -> ErrorBody {
+    ErrorBody {
+        msg_id: None,
+        in_reply_to: Some(MessageId(1)),
+        code: ErrorCode::NotSupported,
+        text: Some(String::from("unknown body type")),
+    }
+}
+
 /* ------ Messages ------ */
 const CLIENT_ID: &str = "c1";
 const NODE_ID: &str = "n1";
@@ -145,16 +205,218 @@ pub const KNOWN_READ_OK_MSG: &str = formatcp!("{RESPONSE_BASE}{KNOWN_READ_OK_BOD
 pub const KNOWN_TOPOLOGY_MSG: &str = formatcp!("{REQUEST_BASE}{KNOWN_TOPOLOGY_BODY}}}");
 pub const KNOWN_TOPOLOGY_OK_MSG: &str = formatcp!("{RESPONSE_BASE}{KNOWN_TOPOLOGY_OK_BODY}}}");
 
+pub const KNOWN_ERROR_MSG: &str = formatcp!("{RESPONSE_BASE}{KNOWN_ERROR_BODY}}}");
+
 pub fn known_request<B: MessageBody>(body: B) -> Message<B> {
-    Message {
-        src: String::from(CLIENT_ID),
-        dest: String::from(NODE_ID),
-        body,
-    }
+    Message::new(CLIENT_ID, NODE_ID, body)
 }
 
 pub fn known_response<B: MessageBody>(body: B) -> Message<B> {
-    let mut msg = known_request(body);
-    msg.into_response(|_| ());
-    msg
+    Message::new(NODE_ID, CLIENT_ID, body)
+}
+
+/// Compares two JSON strings while ignoring the order of object keys and array elements. This is
+/// needed for bodies that contain hash sets and maps, whose serialization order is not stable.
+pub fn unordered_json_eq(a: &str, b: &str) -> bool {
+    fn normalize(val: &mut Value) {
+        match val {
+            Value::Array(vals) => {
+                vals.iter_mut().for_each(normalize);
+                vals.sort_by_key(|val| val.to_string());
+            }
+            Value::Object(map) => map.values_mut().for_each(normalize),
+            _ => {}
+        }
+    }
+    let mut a: Value = serde_json::from_str(a).unwrap();
+    let mut b: Value = serde_json::from_str(b).unwrap();
+    normalize(&mut a);
+    normalize(&mut b);
+    a == b
+}
+
+/* ------ Harness ------ */
+/// Echos messages back.
+pub struct EchoNode;
+
+impl Node for EchoNode {
+    type Body = EchoBody;
+
+    fn init(_: &NodeContext<Self::Body>) -> Self {
+        Self
+    }
+
+    fn handle_msg(
+        &mut self,
+        _: &NodeContext<Self::Body>,
+        mut msg: Message<Self::Body>,
+    ) -> anyhow::Result<Outbox<Self::Body>> {
+        msg.into_response(|body| {
+            if let EchoBody::Echo { echo, .. } = body {
+                *body = EchoBody::EchoOk {
+                    echo: echo.clone(),
+                    msg_id: MessageId::default(),
+                    in_reply_to: MessageId::default(),
+                }
+            }
+        });
+        Ok(msg.into())
+    }
+}
+
+/// The body of a `CommandNode` whose commands use `B`.
+pub type CommandBody<B> = OneOf<EchoBody, B>;
+
+/// The commands that a `CommandNode` runs, which is all that differs between nodes that test
+/// services' clients. `Body` is what the node uses besides the echos that carry the commands.
+pub trait Commands: Sized + Send {
+    type Body: MessageBody;
+
+    fn init(ctx: &NodeContext<CommandBody<Self::Body>>) -> Self;
+
+    /// Runs a command and returns what to echo back.
+    fn run(
+        &mut self,
+        ctx: &NodeContext<CommandBody<Self::Body>>,
+        command: &str,
+    ) -> impl Future<Output = anyhow::Result<String>> + Send;
+}
+
+/// Runs the command in each echo (see `Harness::command`) and echos back the result. Other
+/// messages are ignored.
+pub struct CommandNode<C>(C);
+
+impl<C: Commands> AsyncNode for CommandNode<C> {
+    type Body = CommandBody<C::Body>;
+
+    async fn init(ctx: &NodeContext<Self::Body>) -> Self {
+        Self(C::init(ctx))
+    }
+
+    async fn handle_msg(
+        &mut self,
+        ctx: &NodeContext<Self::Body>,
+        msg: Message<Self::Body>,
+    ) -> anyhow::Result<Outbox<Self::Body>> {
+        let Either::Left(mut msg) = msg.split() else {
+            return Ok(Outbox::new());
+        };
+        let EchoBody::Echo { echo, .. } = msg.body.clone() else {
+            return Ok(Outbox::new());
+        };
+        let echo = self.0.run(ctx, &echo).await?;
+        msg.into_response(|body| {
+            *body = EchoBody::EchoOk {
+                echo,
+                msg_id: MessageId::default(),
+                in_reply_to: MessageId::default(),
+            }
+        });
+        Ok(Outbox::from(msg.map_body(OneOf::left)))
+    }
+}
+
+/// Drives a node over in-memory streams. Commands are sent to the node as echos from `c1`, and
+/// requests that the node makes can be answered.
+pub struct Harness {
+    pub input: WriteHalf<DuplexStream>,
+    output: Lines<BufReader<ReadHalf<DuplexStream>>>,
+    pub handle: JoinHandle<Result<(), Error>>,
+}
+
+impl Harness {
+    /// Starts a node without initializing it.
+    pub fn new<N: AsyncNode + 'static>(config: ClientConfig) -> Self {
+        Self::with_loop(|input, output| main_loop_with_transport::<N, _, _>(input, output, config))
+    }
+
+    /// Starts a node with the given main loop without initializing it.
+    pub fn with_loop<F, L>(main_loop: L) -> Self
+    where
+        L: FnOnce(BufReader<ReadHalf<DuplexStream>>, WriteHalf<DuplexStream>) -> F,
+        F: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let (ours, theirs) = tokio::io::duplex(1 << 16);
+        let (node_input, node_output) = split(theirs);
+        let handle = tokio::spawn(main_loop(BufReader::new(node_input), node_output));
+        let (output, input) = split(ours);
+        Self {
+            input,
+            output: BufReader::new(output).lines(),
+            handle,
+        }
+    }
+
+    /// Starts a node with the default config and sends it the known init message.
+    pub async fn init<N: AsyncNode + 'static>() -> Self {
+        Self::init_with::<N>(ClientConfig::default()).await
+    }
+
+    /// Starts a node with the given config and sends it the known init message.
+    pub async fn init_with<N: AsyncNode + 'static>(config: ClientConfig) -> Self {
+        Self::new::<N>(config).send_init().await
+    }
+
+    /// Starts a concurrent node with the given config and sends it the known init message.
+    pub async fn init_concurrent<N: ConcurrentNode>(config: ClientConfig) -> Self {
+        Self::with_loop(|input, output| {
+            main_loop_concurrent_with_transport::<N, _, _>(input, output, config)
+        })
+        .send_init()
+        .await
+    }
+
+    /// Sends the known init message and checks that the node replies to it.
+    pub async fn send_init(mut self) -> Self {
+        self.send_line(KNOWN_INIT_MSG).await;
+        let init_ok = self.recv().await;
+        assert_eq!(init_ok["body"]["type"], "init_ok");
+        assert_eq!(init_ok["body"]["in_reply_to"], 1);
+        self
+    }
+
+    pub async fn send(&mut self, msg: Value) {
+        self.send_line(&msg.to_string()).await;
+    }
+
+    pub async fn send_line(&mut self, line: &str) {
+        self.input.write_all(line.as_bytes()).await.unwrap();
+        self.input.write_all(b"\n").await.unwrap();
+    }
+
+    pub async fn recv(&mut self) -> Value {
+        let line = self.output.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    /// Sends a command to the node and returns the first message that it sends.
+    pub async fn command(&mut self, command: &str) -> Value {
+        self.send(json!({
+            "src": "c1",
+            "dest": "n3",
+            "body": {"type": "echo", "msg_id": 7, "echo": command},
+        }))
+        .await;
+        self.recv().await
+    }
+
+    /// Replies to a request from the node with the given body.
+    pub async fn respond(&mut self, req: &Value, mut body: Value) {
+        body["in_reply_to"] = req["body"]["msg_id"].clone();
+        self.send(json!({"src": req["dest"], "dest": req["src"], "body": body}))
+            .await;
+    }
+
+    /// Replies to a request from the node and returns the next message that it sends.
+    pub async fn answer(&mut self, req: &Value, body: Value) -> Value {
+        self.respond(req, body).await;
+        self.recv().await
+    }
+
+    /// Replies to a request from the node and returns what the node echoed back.
+    pub async fn reply(&mut self, req: &Value, body: Value) -> Value {
+        let resp = self.answer(req, body).await;
+        assert_eq!(resp["body"]["in_reply_to"], 7);
+        resp["body"]["echo"].clone()
+    }
 }